@@ -0,0 +1,782 @@
+use std::{
+    fs::{self, remove_file},
+    io,
+    os::unix::fs::{chown, symlink, MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use crate::{backups, difference, execution, filesystem, generations, packages, store};
+
+pub const PASSWD_PATH: &str = "/etc/passwd";
+pub const GROUP_PATH: &str = "/etc/group";
+
+pub mod models;
+#[cfg(test)]
+mod tests;
+
+/// Applies every action of a difference. Each completed step is journaled with a backup of
+/// what it replaced, so that when a step fails the completed steps are undone in reverse
+/// order. Without `rollback_on_failure` the system is left as is and the completed steps are
+/// listed instead.
+pub fn apply_difference(
+    difference: &difference::models::Difference,
+    options: &models::Options,
+) -> io::Result<()> {
+    let data_directory = &options.data_directory;
+
+    if !difference.actions.is_empty() {
+        println!("[ Stage 4 ] ( Applying Differences )");
+    } else {
+        println!("[ Stage 4 ] ( No Differences Found )");
+    }
+
+    let initial_created_directories = generations::read_created_directories(data_directory)?;
+    let mut created_directories = initial_created_directories.clone();
+    let mut journal: Vec<models::JournalEntry> = Vec::new();
+
+    let result = apply_actions(
+        &difference.actions,
+        options,
+        &mut created_directories,
+        &mut journal,
+    );
+
+    match result {
+        Ok(()) => {
+            discard_backups(&journal, &mut created_directories)?;
+            generations::write_created_directories(data_directory, &created_directories)
+        }
+        Err(err) if options.rollback_on_failure => {
+            println!("[ Stage 4 ] ( Failed ) {}", err);
+
+            let rollback_result = rollback_journal(journal);
+            generations::write_created_directories(data_directory, &initial_created_directories)?;
+
+            match rollback_result {
+                Ok(()) => Err(io::Error::new(
+                    err.kind(),
+                    format!("{} (completed steps were rolled back)", err),
+                )),
+                Err(rollback_err) => Err(io::Error::new(
+                    err.kind(),
+                    format!("{} (rolling back failed: {})", err, rollback_err),
+                )),
+            }
+        }
+        Err(err) => {
+            println!("[ Stage 4 ] ( Failed ) {}", err);
+
+            for entry in &journal {
+                println!("[ Stage 4 ] ( Completed Step ) {}", entry.description);
+            }
+
+            discard_backups(&journal, &mut created_directories)?;
+            generations::write_created_directories(data_directory, &created_directories)?;
+
+            Err(err)
+        }
+    }
+}
+
+fn apply_actions(
+    actions: &[difference::models::Action],
+    options: &models::Options,
+    created_directories: &mut Vec<PathBuf>,
+    journal: &mut Vec<models::JournalEntry>,
+) -> io::Result<()> {
+    for action in actions {
+        let initial_created_directories = created_directories.clone();
+        let (description, undo) = backup_action(action)?;
+        let mut step_backups = models::StepBackups::default();
+
+        let result = apply_action(
+            action,
+            options,
+            created_directories,
+            &undo,
+            &mut step_backups,
+        );
+
+        // A step that failed half way is journaled as well, so that its partial changes are
+        // undone together with the completed steps.
+        journal.push(models::JournalEntry {
+            description,
+            undo,
+            created_directories: created_directories
+                .iter()
+                .filter(|directory| !initial_created_directories.contains(directory))
+                .cloned()
+                .collect(),
+            backups: step_backups,
+        });
+
+        result?;
+    }
+
+    Ok(())
+}
+
+/// Describes an action and records the state it is about to replace.
+fn backup_action(action: &difference::models::Action) -> io::Result<(String, models::Undo)> {
+    Ok(match action {
+        difference::models::Action::File(file) => {
+            let (method, path) = match file {
+                difference::models::File::Create { path, .. } => ("Create File", path),
+                difference::models::File::Update { path, .. } => ("Update File", path),
+                difference::models::File::Delete { path } => ("Delete File", path),
+                difference::models::File::Unmanage { path } => ("Unmanage File", path),
+            };
+
+            (
+                format!("( {} ) {}", method, path.display()),
+                models::Undo::File {
+                    path: path.clone(),
+                    backup: backup_file(path)?,
+                },
+            )
+        }
+        difference::models::Action::Link(link) => {
+            let (method, path) = match link {
+                difference::models::Link::Create { path, .. } => ("Create Link", path),
+                difference::models::Link::Retarget { path, .. } => ("Retarget Link", path),
+                difference::models::Link::Remove { path } => ("Remove Link", path),
+            };
+
+            (
+                format!("( {} ) {}", method, path.display()),
+                models::Undo::Link {
+                    path: path.clone(),
+                    target: match fs::symlink_metadata(path) {
+                        Ok(metadata) if metadata.is_symlink() => Some(fs::read_link(path)?),
+                        _ => None,
+                    },
+                },
+            )
+        }
+        difference::models::Action::Directory(directory) => match directory {
+            difference::models::Directory::Create { path, .. }
+            | difference::models::Directory::Update { path, .. } => (
+                format!("( Set Directory ) {}", path.display()),
+                models::Undo::Directory {
+                    path: path.clone(),
+                    backup: backup_metadata(path)?,
+                },
+            ),
+            difference::models::Directory::Delete { path } => (
+                format!("( Remove Directory ) {}", path.display()),
+                models::Undo::RemovedDirectory {
+                    path: path.clone(),
+                    backup_path: sibling_path(path, "carbide-backup")?,
+                },
+            ),
+            // Only empty directories are removed when they leave management, so recreating
+            // them is enough to undo it.
+            difference::models::Directory::Unmanage { path } => (
+                format!("( Unmanage Directory ) {}", path.display()),
+                models::Undo::Directory {
+                    path: path.clone(),
+                    backup: backup_metadata(path)?,
+                },
+            ),
+        },
+        difference::models::Action::Package(package) => {
+            let (method, manager, names) = match package {
+                difference::models::Package::Install { manager, names } => {
+                    ("Install Packages", manager, names)
+                }
+                difference::models::Package::Remove { manager, names } => {
+                    ("Remove Packages", manager, names)
+                }
+            };
+
+            (
+                format!("( {} ) {}: {}", method, manager, names.join(" ")),
+                models::Undo::Irreversible,
+            )
+        }
+        difference::models::Action::Service(service) => (
+            difference::format_service(service),
+            models::Undo::Irreversible,
+        ),
+        difference::models::Action::Hook(hook) => (
+            format!("( Run Hook ) {}", hook.command),
+            models::Undo::Irreversible,
+        ),
+        difference::models::Action::Script(script) => (
+            format!(
+                "( Run Script ) {} from generation {}",
+                script.commands().join(" && "),
+                script.generation_id()
+            ),
+            models::Undo::Irreversible,
+        ),
+    })
+}
+
+fn apply_action(
+    action: &difference::models::Action,
+    options: &models::Options,
+    created_directories: &mut Vec<PathBuf>,
+    undo: &models::Undo,
+    step_backups: &mut models::StepBackups,
+) -> io::Result<()> {
+    match action {
+        difference::models::Action::File(file) => match file {
+            difference::models::File::Create {
+                path,
+                hash,
+                permissions,
+            } => {
+                println!("[ Stage 4 ] ( Creating File ) {}", path.display());
+
+                // Backups left from an earlier takeover, which failed to restore them, must not
+                // be restored when this one ends.
+                let earlier_backups = backups::find_backups(&options.data_directory, path)?;
+
+                // Files behind a symlink are written through it, so they are backed up as well.
+                if fs::metadata(path).is_ok_and(|metadata| metadata.is_file()) {
+                    println!("[ Stage 4 ] ( Backing Up File ) {}", path.display());
+
+                    step_backups.created = Some(backups::create_backup(
+                        &options.data_directory,
+                        options.generation_id,
+                        path,
+                    )?);
+                }
+
+                step_backups.discarded = earlier_backups
+                    .into_iter()
+                    .filter(|backup_path| step_backups.created.as_ref() != Some(backup_path))
+                    .collect();
+
+                if let Some(parent) = path.parent() {
+                    create_directory(parent, created_directories)?;
+                }
+
+                filesystem::write_atomically_with_attributes(
+                    path,
+                    &store::read_blob(&options.data_directory, hash)?,
+                    &file_attributes(permissions)?,
+                )?;
+            }
+            difference::models::File::Update {
+                path,
+                hash,
+                permissions,
+            } => {
+                // Deletions made outside carbide are reported as drift, so a missing file is
+                // simply created again.
+                if path.exists() {
+                    println!("[ Stage 4 ] ( Updating File ) {}", path.display());
+                } else {
+                    println!("[ Stage 4 ] ( Creating File ) {}", path.display());
+
+                    if let Some(parent) = path.parent() {
+                        create_directory(parent, created_directories)?;
+                    }
+                }
+
+                filesystem::write_atomically_with_attributes(
+                    path,
+                    &store::read_blob(&options.data_directory, hash)?,
+                    &file_attributes(permissions)?,
+                )?;
+            }
+            difference::models::File::Delete { path } => {
+                println!("[ Stage 4 ] ( Deleting File ) {}", path.display());
+
+                remove_managed_file(path)?;
+                remove_empty_parent_directories(path, created_directories)?;
+            }
+            difference::models::File::Unmanage { path } => {
+                match backups::find_backup(&options.data_directory, path)? {
+                    Some(backup_path) => {
+                        println!("[ Stage 4 ] ( Restoring File ) {}", path.display());
+
+                        backups::restore_backup(&backup_path, path)?;
+                        step_backups.discarded.push(backup_path);
+                    }
+                    None => {
+                        println!("[ Stage 4 ] ( Deleting File ) {}", path.display());
+
+                        remove_managed_file(path)?;
+                        remove_empty_parent_directories(path, created_directories)?;
+                    }
+                }
+            }
+        },
+        difference::models::Action::Link(link) => match link {
+            difference::models::Link::Create { path, target } => {
+                println!(
+                    "[ Stage 4 ] ( Creating Link ) {} -> {}",
+                    path.display(),
+                    target.display()
+                );
+
+                if let Some(parent) = path.parent() {
+                    create_directory(parent, created_directories)?;
+                }
+
+                replace_link(path, target)?;
+            }
+            difference::models::Link::Retarget { path, target } => {
+                println!(
+                    "[ Stage 4 ] ( Retargeting Link ) {} -> {}",
+                    path.display(),
+                    target.display()
+                );
+
+                replace_link(path, target)?;
+            }
+            difference::models::Link::Remove { path } => {
+                println!("[ Stage 4 ] ( Removing Link ) {}", path.display());
+
+                if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_symlink()) {
+                    remove_file(path)?;
+                }
+                remove_empty_parent_directories(path, created_directories)?;
+            }
+        },
+        difference::models::Action::Directory(directory) => match directory {
+            difference::models::Directory::Create { path, permissions } => {
+                println!("[ Stage 4 ] ( Creating Directory ) {}", path.display());
+
+                create_directory(path, created_directories)?;
+                set_permissions(path, permissions)?;
+            }
+            difference::models::Directory::Update { path, permissions } => {
+                println!("[ Stage 4 ] ( Updating Directory ) {}", path.display());
+
+                set_permissions(path, permissions)?;
+            }
+            difference::models::Directory::Delete { path } => {
+                println!("[ Stage 4 ] ( Deleting Directory ) {}", path.display());
+
+                remove_directory(path, undo, created_directories)?;
+            }
+            difference::models::Directory::Unmanage { path } => {
+                if !created_directories.contains(path) {
+                    println!("[ Stage 4 ] ( Unmanaging Directory ) {}", path.display());
+                    return Ok(());
+                }
+
+                // Files carbide never managed may have been put into the directory since.
+                if path.is_dir() && fs::read_dir(path)?.next().is_some() {
+                    println!(
+                        "[ Stage 4 ] ( Warning ) Keeping non-empty directory {}",
+                        path.display()
+                    );
+
+                    forget_directory(path, created_directories);
+                    return Ok(());
+                }
+
+                println!("[ Stage 4 ] ( Deleting Directory ) {}", path.display());
+
+                if path.is_dir() {
+                    fs::remove_dir(path)?;
+                }
+
+                forget_directory(path, created_directories);
+                remove_empty_parent_directories(path, created_directories)?;
+            }
+        },
+        difference::models::Action::Package(package) => {
+            let command = match package {
+                difference::models::Package::Install { manager, names } => {
+                    find_package_manager(options, manager)?.install_command(names)
+                }
+                difference::models::Package::Remove { manager, names } => {
+                    find_package_manager(options, manager)?.remove_command(names)
+                }
+            };
+
+            run_command(options, &command)?;
+        }
+        difference::models::Action::Service(service) => {
+            run_command(options, &options.service_manager.command(service))?;
+        }
+        difference::models::Action::Hook(hook) => {
+            run_command(options, &hook.command)?;
+        }
+        difference::models::Action::Script(script) => {
+            execution::run_script(&options.shell, &options.working_directory, script)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs a package, service or hook command, failing when it exits unsuccessfully.
+fn run_command(options: &models::Options, command: &str) -> io::Result<()> {
+    println!("[ Stage 4 ] ( Running Command ) {}", command);
+
+    let output = execution::run_command(&options.shell, &options.working_directory, command)?;
+
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "Command `{}` failed with {}",
+            command, output.status
+        )));
+    }
+
+    Ok(())
+}
+
+fn find_package_manager<'a>(
+    options: &'a models::Options,
+    name: &str,
+) -> io::Result<&'a dyn packages::PackageManager> {
+    options
+        .package_managers
+        .iter()
+        .find(|manager| manager.name() == name)
+        .map(|manager| manager.as_ref())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No package manager named {}", name),
+            )
+        })
+}
+
+/// Undoes journaled steps in reverse order. Every step is attempted; the first error is
+/// returned.
+fn rollback_journal(journal: Vec<models::JournalEntry>) -> io::Result<()> {
+    let mut first_err = None;
+
+    for entry in journal.into_iter().rev() {
+        println!("[ Stage 4 ] ( Rolling Back ) {}", entry.description);
+
+        if let Err(err) = undo_entry(&entry) {
+            println!(
+                "[ Stage 4 ] ( Rollback Failed ) {}: {}",
+                entry.description, err
+            );
+            first_err.get_or_insert(err);
+        }
+    }
+
+    match first_err {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+fn undo_entry(entry: &models::JournalEntry) -> io::Result<()> {
+    match &entry.undo {
+        models::Undo::File { path, backup } => match backup {
+            Some(backup) => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+
+                filesystem::write_atomically(path, &backup.content)?;
+                restore_metadata(path, &backup.metadata)?;
+            }
+            None => {
+                if fs::symlink_metadata(path).is_ok() {
+                    remove_file(path)?;
+                }
+            }
+        },
+        models::Undo::Link { path, target } => match target {
+            Some(target) => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+
+                replace_link(path, target)?;
+            }
+            None => {
+                if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_symlink()) {
+                    remove_file(path)?;
+                }
+            }
+        },
+        models::Undo::Directory { path, backup } => {
+            if let Some(backup) = backup {
+                fs::create_dir_all(path)?;
+                restore_metadata(path, backup)?;
+            }
+        }
+        models::Undo::RemovedDirectory { path, backup_path } => {
+            if fs::symlink_metadata(backup_path).is_ok() {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+
+                fs::rename(backup_path, path)?;
+            }
+        }
+        models::Undo::Irreversible => {
+            println!("[ Stage 4 ] ( Cannot Roll Back ) {}", entry.description);
+        }
+    }
+
+    // The file is back to what it was, so a later takeover takes a backup of its own.
+    if let Some(backup_path) = &entry.backups.created {
+        backups::delete_backup(backup_path)?;
+    }
+
+    for directory in entry.created_directories.iter().rev() {
+        if directory.is_dir() && fs::read_dir(directory)?.next().is_none() {
+            fs::remove_dir(directory)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the directories that were set aside and the backups that were restored or made
+/// obsolete by completed steps, along with parents that carbide created and that are empty now.
+fn discard_backups(
+    journal: &[models::JournalEntry],
+    created_directories: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    for entry in journal {
+        for backup_path in &entry.backups.discarded {
+            backups::delete_backup(backup_path)?;
+        }
+
+        if let models::Undo::RemovedDirectory { backup_path, .. } = &entry.undo {
+            if fs::symlink_metadata(backup_path).is_ok() {
+                fs::remove_dir_all(backup_path)?;
+                remove_empty_parent_directories(backup_path, created_directories)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Sets a directory aside instead of deleting it, so that it can be restored on rollback.
+fn remove_directory(
+    path: &Path,
+    undo: &models::Undo,
+    created_directories: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    if path.exists() {
+        match undo {
+            models::Undo::RemovedDirectory { backup_path, .. } => {
+                if fs::symlink_metadata(backup_path).is_ok() {
+                    fs::remove_dir_all(backup_path)?;
+                }
+                fs::rename(path, backup_path)?;
+            }
+            _ => fs::remove_dir_all(path)?,
+        }
+    }
+
+    forget_directory(path, created_directories);
+    remove_empty_parent_directories(path, created_directories)
+}
+
+/// Deletes a managed file, which counts as done when it was already deleted outside carbide.
+fn remove_managed_file(path: &Path) -> io::Result<()> {
+    match remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(err) => match err.kind() {
+            io::ErrorKind::NotFound => Ok(()),
+            _ => Err(io::Error::new(
+                err.kind(),
+                format!("Error deleting {}: {}", path.display(), err),
+            )),
+        },
+    }
+}
+
+fn backup_file(path: &Path) -> io::Result<Option<models::FileBackup>> {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.is_file() => Ok(Some(models::FileBackup {
+            content: fs::read(path)?,
+            metadata: models::MetadataBackup {
+                mode: metadata.mode(),
+                uid: metadata.uid(),
+                gid: metadata.gid(),
+            },
+        })),
+        Ok(_) => Ok(None),
+        Err(err) => match err.kind() {
+            io::ErrorKind::NotFound => Ok(None),
+            _ => Err(err),
+        },
+    }
+}
+
+fn backup_metadata(path: &Path) -> io::Result<Option<models::MetadataBackup>> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(Some(models::MetadataBackup {
+            mode: metadata.mode(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+        })),
+        Err(err) => match err.kind() {
+            io::ErrorKind::NotFound => Ok(None),
+            _ => Err(err),
+        },
+    }
+}
+
+fn restore_metadata(path: &Path, backup: &models::MetadataBackup) -> io::Result<()> {
+    chown(path, Some(backup.uid), Some(backup.gid))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(backup.mode & 0o7777))
+}
+
+/// Returns a hidden path next to `path`, e.g. `/etc/.nginx.carbide-backup`.
+fn sibling_path(path: &Path, suffix: &str) -> io::Result<PathBuf> {
+    let file_name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid path: {}", path.display()),
+        )
+    })?;
+
+    Ok(path.with_file_name(format!(".{}.{}", file_name.to_string_lossy(), suffix)))
+}
+
+/// Points a symlink at a target by renaming a new symlink over the old one. Refuses to
+/// replace anything that is not a symlink.
+fn replace_link(path: &Path, target: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.is_symlink() => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "Cannot create link {}: a non-link file already exists at that path",
+                    path.display()
+                ),
+            ))
+        }
+        Ok(_) => {}
+        Err(err) => match err.kind() {
+            io::ErrorKind::NotFound => {}
+            _ => return Err(err),
+        },
+    }
+
+    let file_name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid link path: {}", path.display()),
+        )
+    })?;
+    let temporary_path =
+        path.with_file_name(format!(".{}.carbide-link", file_name.to_string_lossy()));
+
+    if fs::symlink_metadata(&temporary_path).is_ok() {
+        remove_file(&temporary_path)?;
+    }
+    symlink(target, &temporary_path)?;
+    fs::rename(&temporary_path, path)
+}
+
+/// Creates a directory and any missing parents, recording every directory it creates.
+fn create_directory(path: &Path, created_directories: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut missing_directories = Vec::new();
+
+    for ancestor in path.ancestors() {
+        if ancestor.as_os_str().is_empty() || ancestor.exists() {
+            break;
+        }
+
+        missing_directories.push(ancestor);
+    }
+
+    for directory in missing_directories.into_iter().rev() {
+        fs::create_dir(directory)?;
+        created_directories.push(directory.to_path_buf());
+    }
+
+    Ok(())
+}
+
+/// Removes the parents of a path that carbide created and that are now empty.
+fn remove_empty_parent_directories(
+    path: &Path,
+    created_directories: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    for ancestor in path.ancestors().skip(1) {
+        if !created_directories
+            .iter()
+            .any(|directory| directory == ancestor)
+        {
+            break;
+        }
+
+        if fs::read_dir(ancestor)?.next().is_some() {
+            break;
+        }
+
+        fs::remove_dir(ancestor)?;
+        forget_directory(ancestor, created_directories);
+    }
+
+    Ok(())
+}
+
+/// Drops a removed directory and everything below it from the created directories.
+fn forget_directory(path: &Path, created_directories: &mut Vec<PathBuf>) {
+    created_directories.retain(|directory| !directory.starts_with(path));
+}
+
+pub fn set_permissions(
+    path: &Path,
+    permissions: &generations::models::Permissions,
+) -> io::Result<()> {
+    let attributes = file_attributes(permissions)?;
+
+    if attributes.uid.is_some() || attributes.gid.is_some() {
+        chown(path, attributes.uid, attributes.gid)?;
+    }
+
+    if let Some(mode) = attributes.mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+
+    Ok(())
+}
+
+/// Resolves the owner and group names of managed permissions to IDs. Files get these
+/// attributes while they are written, so their content is never exposed with other ones.
+fn file_attributes(
+    permissions: &generations::models::Permissions,
+) -> io::Result<filesystem::Attributes> {
+    Ok(filesystem::Attributes {
+        mode: permissions.mode,
+        uid: match &permissions.owner {
+            Some(owner) => Some(lookup_id(Path::new(PASSWD_PATH), owner)?),
+            None => None,
+        },
+        gid: match &permissions.group {
+            Some(group) => Some(lookup_id(Path::new(GROUP_PATH), group)?),
+            None => None,
+        },
+    })
+}
+
+/// Resolves a user or group name to its ID using a passwd or group style database. Numeric
+/// names are used as IDs directly.
+pub fn lookup_id(database: &Path, name: &str) -> io::Result<u32> {
+    if let Ok(id) = name.parse::<u32>() {
+        return Ok(id);
+    }
+
+    for line in fs::read_to_string(database)?.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+
+        if fields.len() > 2 && fields[0] == name {
+            return fields[2].parse::<u32>().map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Error parsing id of {}: {}", name, err),
+                )
+            });
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("No entry named {} in {}", name, database.display()),
+    ))
+}
@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+
+use crate::{execution, packages, services};
+
+pub struct Options {
+    pub shell: execution::models::Shell,
+    /// Directory script commands run in.
+    pub working_directory: PathBuf,
+    pub data_directory: PathBuf,
+    /// ID of the generation being applied.
+    pub generation_id: i32,
+    /// Undo completed steps when a step fails.
+    pub rollback_on_failure: bool,
+    /// Backends package actions can be run with, looked up by name.
+    pub package_managers: Vec<Box<dyn packages::PackageManager>>,
+    /// Backend service actions are run with.
+    pub service_manager: Box<dyn services::ServiceManager>,
+}
+
+/// A completed apply step and what is needed to undo it.
+#[derive(Debug)]
+pub struct JournalEntry {
+    pub description: String,
+    pub undo: Undo,
+    /// Directories created by the step, removed again when the step is undone.
+    pub created_directories: Vec<PathBuf>,
+    pub backups: StepBackups,
+}
+
+/// Backups of taken over files that a step wrote or made obsolete.
+#[derive(Debug, Default)]
+pub struct StepBackups {
+    /// Backup taken by the step, removed again when the step is undone.
+    pub created: Option<PathBuf>,
+    /// Backups that were restored or belong to an earlier takeover, only removed once the
+    /// whole apply succeeded.
+    pub discarded: Vec<PathBuf>,
+}
+
+#[derive(Debug)]
+pub enum Undo {
+    /// Restores the file to its backup, or removes it when it did not exist.
+    File {
+        path: PathBuf,
+        backup: Option<FileBackup>,
+    },
+    /// Points the link back at its previous target, or removes it when it did not exist.
+    Link {
+        path: PathBuf,
+        target: Option<PathBuf>,
+    },
+    /// Restores the mode and ownership of a directory that already existed, recreating it
+    /// when it was removed.
+    Directory {
+        path: PathBuf,
+        backup: Option<MetadataBackup>,
+    },
+    /// Moves a removed directory back from where it was set aside.
+    RemovedDirectory { path: PathBuf, backup_path: PathBuf },
+    /// Script commands cannot be undone.
+    Irreversible,
+}
+
+#[derive(Debug)]
+pub struct FileBackup {
+    pub content: Vec<u8>,
+    pub metadata: MetadataBackup,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MetadataBackup {
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
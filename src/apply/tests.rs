@@ -0,0 +1,697 @@
+use std::{
+    fs,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::PathBuf,
+};
+
+use assert_fs::prelude::*;
+
+use crate::{
+    apply::{self, models::Options},
+    backups,
+    difference::models::{Action, Difference, Directory, File, Link, Package, Script, Service},
+    execution::models::Shell,
+    generations, packages, services, store,
+};
+
+#[test]
+fn apply_difference() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let update_file = root.child("update");
+    update_file.write_str("Initial Content").unwrap();
+    let delete_file = root.child("delete");
+    delete_file.write_str("Hello World").unwrap();
+
+    let difference = Difference {
+        actions: vec![
+            Action::File(File::Create {
+                path: root.child("create").to_path_buf(),
+                hash: store::write_blob(root.child("data").path(), b"New Content").unwrap(),
+                permissions: generations::models::Permissions {
+                    mode: Some(0o600),
+                    owner: None,
+                    group: None,
+                },
+            }),
+            Action::File(File::Update {
+                path: update_file.to_path_buf(),
+                hash: store::write_blob(root.child("data").path(), b"Final Content").unwrap(),
+                permissions: generations::models::Permissions::default(),
+            }),
+            Action::File(File::Delete {
+                path: delete_file.to_path_buf(),
+            }),
+            Action::Script(Script::Install {
+                generation_id: 1,
+                commands: vec![String::from("echo Hello > script")],
+            }),
+        ],
+    };
+
+    apply::apply_difference(
+        &difference,
+        &Options {
+            shell: Shell::new(PathBuf::from("/bin/sh")),
+            working_directory: root.path().to_path_buf(),
+            data_directory: root.child("data").path().to_path_buf(),
+            generation_id: 1,
+            rollback_on_failure: true,
+            package_managers: Vec::new(),
+            service_manager: Box::new(services::Systemctl),
+        },
+    )
+    .unwrap();
+
+    root.child("create").assert("New Content");
+    assert_eq!(
+        fs::metadata(root.child("create").path())
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o7777,
+        0o600
+    );
+    update_file.assert("Final Content");
+    assert!(!delete_file.path().exists());
+    root.child("script").assert("Hello\n");
+}
+
+#[test]
+fn apply_difference_updates_missing_file() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let file = root.child("parent/file");
+
+    apply::apply_difference(
+        &Difference {
+            actions: vec![Action::File(File::Update {
+                path: file.to_path_buf(),
+                hash: store::write_blob(root.child("data").path(), b"Final Content").unwrap(),
+                permissions: generations::models::Permissions::default(),
+            })],
+        },
+        &Options {
+            shell: Shell::new(PathBuf::from("/bin/sh")),
+            working_directory: root.to_path_buf(),
+            data_directory: root.child("data").to_path_buf(),
+            generation_id: 1,
+            rollback_on_failure: true,
+            package_managers: Vec::new(),
+            service_manager: Box::new(services::Systemctl),
+        },
+    )
+    .unwrap();
+
+    file.assert("Final Content");
+}
+
+#[test]
+fn apply_difference_removes_missing_files() {
+    let root = assert_fs::TempDir::new().unwrap();
+
+    apply::apply_difference(
+        &Difference {
+            actions: vec![
+                Action::File(File::Delete {
+                    path: root.child("deleted").to_path_buf(),
+                }),
+                Action::File(File::Unmanage {
+                    path: root.child("unmanaged").to_path_buf(),
+                }),
+            ],
+        },
+        &Options {
+            shell: Shell::new(PathBuf::from("/bin/sh")),
+            working_directory: root.to_path_buf(),
+            data_directory: root.child("data").to_path_buf(),
+            generation_id: 1,
+            rollback_on_failure: true,
+            package_managers: Vec::new(),
+            service_manager: Box::new(services::Systemctl),
+        },
+    )
+    .unwrap();
+}
+
+#[test]
+fn apply_difference_backs_up_unmanaged_files() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let data_directory = root.child("data");
+    let file = root.child("nginx.conf");
+    file.write_str("Original Content").unwrap();
+    let options = Options {
+        shell: Shell::new(PathBuf::from("/bin/sh")),
+        working_directory: root.to_path_buf(),
+        data_directory: data_directory.to_path_buf(),
+        generation_id: 1,
+        rollback_on_failure: true,
+        package_managers: Vec::new(),
+        service_manager: Box::new(services::Systemctl),
+    };
+
+    let difference = Difference {
+        actions: vec![Action::File(File::Create {
+            path: file.to_path_buf(),
+            hash: store::write_blob(data_directory.path(), b"Managed Content").unwrap(),
+            permissions: generations::models::Permissions::default(),
+        })],
+    };
+
+    apply::apply_difference(&difference, &options).unwrap();
+
+    file.assert("Managed Content");
+    assert_eq!(
+        fs::read_to_string(backups::backup_path(data_directory.path(), 1, file.path())).unwrap(),
+        "Original Content"
+    );
+
+    // A failing step after the restore puts the managed content back and keeps the backup.
+    let difference = Difference {
+        actions: vec![
+            Action::File(File::Unmanage {
+                path: file.to_path_buf(),
+            }),
+            Action::Script(Script::Install {
+                generation_id: 2,
+                commands: vec![String::from("false")],
+            }),
+        ],
+    };
+
+    assert!(apply::apply_difference(&difference, &options).is_err());
+
+    file.assert("Managed Content");
+
+    let difference = Difference {
+        actions: vec![Action::File(File::Unmanage {
+            path: file.to_path_buf(),
+        })],
+    };
+
+    apply::apply_difference(&difference, &options).unwrap();
+
+    file.assert("Original Content");
+    assert_eq!(
+        backups::find_backup(data_directory.path(), file.path()).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn apply_difference_discards_stale_backups() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let data_directory = root.child("data");
+    let file = root.child("nginx.conf");
+    file.write_str("Original Content").unwrap();
+    let options = |generation_id| Options {
+        shell: Shell::new(PathBuf::from("/bin/sh")),
+        working_directory: root.to_path_buf(),
+        data_directory: data_directory.to_path_buf(),
+        generation_id,
+        rollback_on_failure: true,
+        package_managers: Vec::new(),
+        service_manager: Box::new(services::Systemctl),
+    };
+    let create = || {
+        Action::File(File::Create {
+            path: file.to_path_buf(),
+            hash: store::write_blob(data_directory.path(), b"Managed Content").unwrap(),
+            permissions: generations::models::Permissions::default(),
+        })
+    };
+
+    // A rolled back takeover leaves no backup behind.
+    let difference = Difference {
+        actions: vec![
+            create(),
+            Action::Script(Script::Install {
+                generation_id: 1,
+                commands: vec![String::from("false")],
+            }),
+        ],
+    };
+
+    assert!(apply::apply_difference(&difference, &options(1)).is_err());
+
+    file.assert("Original Content");
+    assert_eq!(
+        backups::find_backup(data_directory.path(), file.path()).unwrap(),
+        None
+    );
+
+    // A backup left by an earlier takeover is not restored when a later one ends.
+    backups::create_backup(data_directory.path(), 1, file.path()).unwrap();
+    fs::remove_file(file.path()).unwrap();
+
+    let difference = Difference {
+        actions: vec![create()],
+    };
+
+    apply::apply_difference(&difference, &options(2)).unwrap();
+
+    assert_eq!(
+        backups::find_backup(data_directory.path(), file.path()).unwrap(),
+        None
+    );
+
+    let difference = Difference {
+        actions: vec![Action::File(File::Unmanage {
+            path: file.to_path_buf(),
+        })],
+    };
+
+    apply::apply_difference(&difference, &options(3)).unwrap();
+
+    assert!(!file.exists());
+}
+
+#[test]
+fn apply_difference_directories() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let data_directory = root.child("data");
+    let existing_directory = root.child("existing");
+    existing_directory.create_dir_all().unwrap();
+    let options = Options {
+        shell: Shell::new(PathBuf::from("/bin/sh")),
+        working_directory: root.to_path_buf(),
+        data_directory: data_directory.to_path_buf(),
+        generation_id: 1,
+        rollback_on_failure: true,
+        package_managers: Vec::new(),
+        service_manager: Box::new(services::Systemctl),
+    };
+
+    let difference = Difference {
+        actions: vec![
+            Action::Directory(Directory::Create {
+                path: root.child("created/nested").to_path_buf(),
+                permissions: generations::models::Permissions::default(),
+            }),
+            Action::Directory(Directory::Create {
+                path: existing_directory.to_path_buf(),
+                permissions: generations::models::Permissions::default(),
+            }),
+            Action::File(File::Create {
+                path: root.child("parent/file").to_path_buf(),
+                hash: store::write_blob(data_directory.path(), b"Hello World").unwrap(),
+                permissions: generations::models::Permissions::default(),
+            }),
+        ],
+    };
+
+    apply::apply_difference(&difference, &options).unwrap();
+
+    assert!(root.child("created/nested").path().is_dir());
+    root.child("parent/file").assert("Hello World");
+
+    let difference = Difference {
+        actions: vec![
+            Action::File(File::Delete {
+                path: root.child("parent/file").to_path_buf(),
+            }),
+            Action::Directory(Directory::Unmanage {
+                path: root.child("created/nested").to_path_buf(),
+            }),
+            Action::Directory(Directory::Unmanage {
+                path: existing_directory.to_path_buf(),
+            }),
+        ],
+    };
+
+    apply::apply_difference(&difference, &options).unwrap();
+
+    assert!(!root.child("created").path().exists());
+    assert!(!root.child("parent").path().exists());
+    assert!(existing_directory.path().is_dir());
+    assert_eq!(
+        generations::read_created_directories(data_directory.path()).unwrap(),
+        Vec::<PathBuf>::new()
+    );
+}
+
+#[test]
+fn apply_difference_keeps_unmanaged_files_in_directories() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let data_directory = root.child("data");
+    let directory = root.child("directory");
+    let options = Options {
+        shell: Shell::new(PathBuf::from("/bin/sh")),
+        working_directory: root.to_path_buf(),
+        data_directory: data_directory.to_path_buf(),
+        generation_id: 1,
+        rollback_on_failure: true,
+        package_managers: Vec::new(),
+        service_manager: Box::new(services::Systemctl),
+    };
+
+    apply::apply_difference(
+        &Difference {
+            actions: vec![Action::Directory(Directory::Create {
+                path: directory.to_path_buf(),
+                permissions: generations::models::Permissions::default(),
+            })],
+        },
+        &options,
+    )
+    .unwrap();
+
+    directory.child("unmanaged").write_str("Local").unwrap();
+
+    apply::apply_difference(
+        &Difference {
+            actions: vec![Action::Directory(Directory::Unmanage {
+                path: directory.to_path_buf(),
+            })],
+        },
+        &options,
+    )
+    .unwrap();
+
+    directory.child("unmanaged").assert("Local");
+    assert_eq!(fs::read_dir(root.path()).unwrap().count(), 2);
+}
+
+#[test]
+fn apply_difference_links() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let data_directory = root.child("data");
+    let options = Options {
+        shell: Shell::new(PathBuf::from("/bin/sh")),
+        working_directory: root.to_path_buf(),
+        data_directory: data_directory.to_path_buf(),
+        generation_id: 1,
+        rollback_on_failure: true,
+        package_managers: Vec::new(),
+        service_manager: Box::new(services::Systemctl),
+    };
+    let link = root.child("config/nvim");
+
+    let difference = Difference {
+        actions: vec![Action::Link(Link::Create {
+            path: link.to_path_buf(),
+            target: PathBuf::from("/home/carbide/dotfiles/nvim"),
+        })],
+    };
+
+    apply::apply_difference(&difference, &options).unwrap();
+
+    assert_eq!(
+        fs::read_link(link.path()).unwrap(),
+        PathBuf::from("/home/carbide/dotfiles/nvim")
+    );
+
+    let difference = Difference {
+        actions: vec![Action::Link(Link::Retarget {
+            path: link.to_path_buf(),
+            target: PathBuf::from("/home/carbide/dotfiles/nvim_2"),
+        })],
+    };
+
+    apply::apply_difference(&difference, &options).unwrap();
+
+    assert_eq!(
+        fs::read_link(link.path()).unwrap(),
+        PathBuf::from("/home/carbide/dotfiles/nvim_2")
+    );
+
+    let difference = Difference {
+        actions: vec![Action::Link(Link::Remove {
+            path: link.to_path_buf(),
+        })],
+    };
+
+    apply::apply_difference(&difference, &options).unwrap();
+
+    assert!(!root.child("config").path().exists());
+}
+
+#[test]
+fn apply_difference_link_conflict() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let file = root.child("bashrc");
+    file.write_str("Hello World").unwrap();
+
+    let difference = Difference {
+        actions: vec![Action::Link(Link::Create {
+            path: file.to_path_buf(),
+            target: PathBuf::from("/home/carbide/dotfiles/bashrc"),
+        })],
+    };
+
+    let err = apply::apply_difference(
+        &difference,
+        &Options {
+            shell: Shell::new(PathBuf::from("/bin/sh")),
+            working_directory: root.path().to_path_buf(),
+            data_directory: root.child("data").path().to_path_buf(),
+            generation_id: 1,
+            rollback_on_failure: true,
+            package_managers: Vec::new(),
+            service_manager: Box::new(services::Systemctl),
+        },
+    )
+    .unwrap_err();
+
+    assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+    file.assert("Hello World");
+}
+
+#[test]
+fn apply_difference_rollback() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let data_directory = root.child("data");
+    let update_file = root.child("update");
+    update_file.write_str("Initial Content").unwrap();
+    fs::set_permissions(update_file.path(), fs::Permissions::from_mode(0o640)).unwrap();
+    let delete_file = root.child("delete");
+    delete_file.write_str("Hello World").unwrap();
+    let delete_directory = root.child("directory");
+    delete_directory
+        .child("file")
+        .write_str("Hello World")
+        .unwrap();
+
+    let difference = Difference {
+        actions: vec![
+            Action::File(File::Create {
+                path: root.child("parent/create").to_path_buf(),
+                hash: store::write_blob(data_directory.path(), b"New Content").unwrap(),
+                permissions: generations::models::Permissions::default(),
+            }),
+            Action::File(File::Update {
+                path: update_file.to_path_buf(),
+                hash: store::write_blob(data_directory.path(), b"Final Content").unwrap(),
+                permissions: generations::models::Permissions {
+                    mode: Some(0o600),
+                    owner: None,
+                    group: None,
+                },
+            }),
+            Action::File(File::Delete {
+                path: delete_file.to_path_buf(),
+            }),
+            Action::Directory(Directory::Delete {
+                path: delete_directory.to_path_buf(),
+            }),
+            Action::Link(Link::Create {
+                path: root.child("link").to_path_buf(),
+                target: PathBuf::from("/home/carbide/dotfiles/nvim"),
+            }),
+            Action::Script(Script::Install {
+                generation_id: 1,
+                commands: vec![String::from("false")],
+            }),
+        ],
+    };
+
+    let err = apply::apply_difference(
+        &difference,
+        &Options {
+            shell: Shell::new(PathBuf::from("/bin/sh")),
+            working_directory: root.path().to_path_buf(),
+            data_directory: data_directory.path().to_path_buf(),
+            generation_id: 1,
+            rollback_on_failure: true,
+            package_managers: Vec::new(),
+            service_manager: Box::new(services::Systemctl),
+        },
+    )
+    .unwrap_err();
+
+    assert!(err.to_string().contains("rolled back"));
+    assert!(!root.child("parent").path().exists());
+    update_file.assert("Initial Content");
+    assert_eq!(
+        fs::metadata(update_file.path())
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o7777,
+        0o640
+    );
+    delete_file.assert("Hello World");
+    delete_directory.child("file").assert("Hello World");
+    assert!(fs::symlink_metadata(root.child("link").path()).is_err());
+    assert_eq!(
+        generations::read_created_directories(data_directory.path()).unwrap(),
+        Vec::<PathBuf>::new()
+    );
+}
+
+#[test]
+fn apply_difference_without_rollback() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let data_directory = root.child("data");
+
+    let difference = Difference {
+        actions: vec![
+            Action::File(File::Create {
+                path: root.child("parent/create").to_path_buf(),
+                hash: store::write_blob(data_directory.path(), b"New Content").unwrap(),
+                permissions: generations::models::Permissions::default(),
+            }),
+            Action::Script(Script::Install {
+                generation_id: 1,
+                commands: vec![String::from("false")],
+            }),
+        ],
+    };
+
+    apply::apply_difference(
+        &difference,
+        &Options {
+            shell: Shell::new(PathBuf::from("/bin/sh")),
+            working_directory: root.path().to_path_buf(),
+            data_directory: data_directory.path().to_path_buf(),
+            generation_id: 1,
+            rollback_on_failure: false,
+            package_managers: Vec::new(),
+            service_manager: Box::new(services::Systemctl),
+        },
+    )
+    .unwrap_err();
+
+    root.child("parent/create").assert("New Content");
+    assert_eq!(
+        generations::read_created_directories(data_directory.path()).unwrap(),
+        vec![root.child("parent").to_path_buf()]
+    );
+}
+
+#[test]
+fn apply_difference_packages() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let options = Options {
+        shell: Shell::new(PathBuf::from("/bin/sh")),
+        working_directory: root.to_path_buf(),
+        data_directory: root.child("data").to_path_buf(),
+        generation_id: 1,
+        rollback_on_failure: true,
+        package_managers: vec![Box::new(packages::Fake {
+            name: String::from("fake"),
+            install: String::from("echo install >> log"),
+            remove: String::from("echo remove >> log"),
+        })],
+        service_manager: Box::new(services::Systemctl),
+    };
+
+    apply::apply_difference(
+        &Difference {
+            actions: vec![
+                Action::Package(Package::Install {
+                    manager: String::from("fake"),
+                    names: vec![String::from("neovim"), String::from("ripgrep")],
+                }),
+                Action::Package(Package::Remove {
+                    manager: String::from("fake"),
+                    names: vec![String::from("docker")],
+                }),
+            ],
+        },
+        &options,
+    )
+    .unwrap();
+
+    root.child("log")
+        .assert("install neovim ripgrep\nremove docker\n");
+
+    let err = apply::apply_difference(
+        &Difference {
+            actions: vec![Action::Package(Package::Install {
+                manager: String::from("apt"),
+                names: vec![String::from("neovim")],
+            })],
+        },
+        &options,
+    )
+    .unwrap_err();
+
+    assert!(err.to_string().contains("No package manager named apt"));
+}
+
+#[test]
+fn apply_difference_services() {
+    let root = assert_fs::TempDir::new().unwrap();
+
+    apply::apply_difference(
+        &Difference {
+            actions: vec![
+                Action::Service(Service::DaemonReload),
+                Action::Service(Service::Enable {
+                    name: String::from("nginx.service"),
+                }),
+                Action::Service(Service::Restart {
+                    name: String::from("nginx.service"),
+                }),
+            ],
+        },
+        &Options {
+            shell: Shell::new(PathBuf::from("/bin/sh")),
+            working_directory: root.to_path_buf(),
+            data_directory: root.child("data").to_path_buf(),
+            generation_id: 1,
+            rollback_on_failure: true,
+            package_managers: Vec::new(),
+            service_manager: Box::new(services::Fake {
+                program: String::from("echo >> log"),
+            }),
+        },
+    )
+    .unwrap();
+
+    root.child("log")
+        .assert("daemon-reload\nenable nginx.service\nrestart nginx.service\n");
+}
+
+#[test]
+fn set_permissions() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let file = root.child("secret");
+    file.write_str("Hello World").unwrap();
+    let metadata = fs::metadata(file.path()).unwrap();
+
+    apply::set_permissions(
+        file.path(),
+        &generations::models::Permissions {
+            mode: Some(0o600),
+            owner: Some(metadata.uid().to_string()),
+            group: Some(metadata.gid().to_string()),
+        },
+    )
+    .unwrap();
+
+    let metadata = fs::metadata(file.path()).unwrap();
+    assert_eq!(metadata.permissions().mode() & 0o7777, 0o600);
+}
+
+#[test]
+fn lookup_id() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let passwd = root.child("passwd");
+    passwd
+        .write_str("root:x:0:0:root:/root:/bin/sh\nnandesh:x:1000:1000::/home/nandesh:/bin/sh\n")
+        .unwrap();
+
+    assert_eq!(apply::lookup_id(passwd.path(), "nandesh").unwrap(), 1000);
+    assert_eq!(apply::lookup_id(passwd.path(), "42").unwrap(), 42);
+    assert!(apply::lookup_id(passwd.path(), "nobody").is_err());
+}
@@ -0,0 +1,114 @@
+use std::{
+    fs, io,
+    os::unix::fs::{chown, MetadataExt},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::filesystem;
+
+#[cfg(test)]
+mod tests;
+
+const BACKUPS_DIRECTORY_NAME: &str = "backups";
+
+/// Returns where the original of `path` is kept when it is first taken over by a generation,
+/// e.g. `backups/3/etc/nginx/nginx.conf`.
+pub fn backup_path(data_directory: &Path, generation_id: i32, path: &Path) -> PathBuf {
+    let mut backup_path = data_directory
+        .join(BACKUPS_DIRECTORY_NAME)
+        .join(generation_id.to_string());
+
+    for component in path.components() {
+        if let Component::Normal(component) = component {
+            backup_path.push(component);
+        }
+    }
+
+    backup_path
+}
+
+/// Saves a copy of a file that carbide is about to take over, keeping its mode and
+/// ownership, and returns where it was saved.
+pub fn create_backup(
+    data_directory: &Path,
+    generation_id: i32,
+    path: &Path,
+) -> io::Result<PathBuf> {
+    let backup_path = backup_path(data_directory, generation_id, path);
+    let metadata = fs::metadata(path)?;
+
+    if let Some(parent) = backup_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    filesystem::write_atomically(&backup_path, &fs::read(path)?)?;
+    fs::set_permissions(&backup_path, metadata.permissions())?;
+    chown(&backup_path, Some(metadata.uid()), Some(metadata.gid()))?;
+
+    Ok(backup_path)
+}
+
+/// Returns the most recent backup of `path`, if any. Taking a file over again discards the
+/// backups of earlier takeovers, so this is the backup of the current one.
+pub fn find_backup(data_directory: &Path, path: &Path) -> io::Result<Option<PathBuf>> {
+    Ok(find_backups(data_directory, path)?.into_iter().next())
+}
+
+/// Returns every backup of `path`, the most recent first.
+pub fn find_backups(data_directory: &Path, path: &Path) -> io::Result<Vec<PathBuf>> {
+    let backups_directory = data_directory.join(BACKUPS_DIRECTORY_NAME);
+
+    let mut generation_ids = Vec::new();
+    match fs::read_dir(&backups_directory) {
+        Ok(entries) => {
+            for entry in entries {
+                if let Some(id) = entry?
+                    .file_name()
+                    .to_str()
+                    .and_then(|id| id.parse::<i32>().ok())
+                {
+                    generation_ids.push(id);
+                }
+            }
+        }
+        Err(err) => match err.kind() {
+            io::ErrorKind::NotFound => return Ok(Vec::new()),
+            _ => return Err(err),
+        },
+    }
+
+    generation_ids.sort_unstable();
+
+    Ok(generation_ids
+        .into_iter()
+        .rev()
+        .map(|id| backup_path(data_directory, id, path))
+        .filter(|backup_path| backup_path.is_file())
+        .collect())
+}
+
+/// Copies a backup back in place of `path`. The backup is kept, so that the restore can still
+/// be undone, until `delete_backup` removes it.
+pub fn restore_backup(backup_path: &Path, path: &Path) -> io::Result<()> {
+    let metadata = fs::metadata(backup_path)?;
+
+    filesystem::write_atomically_with_attributes(
+        path,
+        &fs::read(backup_path)?,
+        &filesystem::Attributes {
+            mode: Some(metadata.mode()),
+            uid: Some(metadata.uid()),
+            gid: Some(metadata.gid()),
+        },
+    )
+}
+
+pub fn delete_backup(backup_path: &Path) -> io::Result<()> {
+    match fs::remove_file(backup_path) {
+        Ok(()) => Ok(()),
+        Err(err) => match err.kind() {
+            io::ErrorKind::NotFound => Ok(()),
+            _ => Err(err),
+        },
+    }
+}
@@ -0,0 +1,80 @@
+use std::{fs, os::unix::fs::PermissionsExt, path::PathBuf};
+
+use assert_fs::prelude::*;
+
+use crate::backups;
+
+#[test]
+fn backup_path() {
+    assert_eq!(
+        backups::backup_path(
+            &PathBuf::from("/var/lib/carbide"),
+            3,
+            &PathBuf::from("/etc/nginx/nginx.conf")
+        ),
+        PathBuf::from("/var/lib/carbide/backups/3/etc/nginx/nginx.conf")
+    );
+}
+
+#[test]
+fn create_and_restore_backup() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let data_directory = root.child("data");
+    let file = root.child("nginx.conf");
+    file.write_str("Original Content").unwrap();
+    fs::set_permissions(file.path(), fs::Permissions::from_mode(0o640)).unwrap();
+
+    backups::create_backup(data_directory.path(), 1, file.path()).unwrap();
+    file.write_str("Managed Content").unwrap();
+    fs::set_permissions(file.path(), fs::Permissions::from_mode(0o644)).unwrap();
+
+    let backup_path = backups::find_backup(data_directory.path(), file.path())
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        backup_path,
+        backups::backup_path(data_directory.path(), 1, file.path())
+    );
+
+    backups::restore_backup(&backup_path, file.path()).unwrap();
+
+    file.assert("Original Content");
+    assert_eq!(
+        fs::metadata(file.path()).unwrap().permissions().mode() & 0o7777,
+        0o640
+    );
+    assert_eq!(
+        backups::find_backup(data_directory.path(), file.path()).unwrap(),
+        Some(backup_path.clone())
+    );
+
+    backups::delete_backup(&backup_path).unwrap();
+
+    assert_eq!(
+        backups::find_backup(data_directory.path(), file.path()).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn find_latest_backup() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let data_directory = root.child("data");
+    let file = root.child("nginx.conf");
+    file.write_str("Original Content").unwrap();
+
+    backups::create_backup(data_directory.path(), 2, file.path()).unwrap();
+    backups::create_backup(data_directory.path(), 10, file.path()).unwrap();
+
+    assert_eq!(
+        backups::find_backup(data_directory.path(), file.path()).unwrap(),
+        Some(backups::backup_path(data_directory.path(), 10, file.path()))
+    );
+    assert_eq!(
+        backups::find_backups(data_directory.path(), file.path()).unwrap(),
+        vec![
+            backups::backup_path(data_directory.path(), 10, file.path()),
+            backups::backup_path(data_directory.path(), 2, file.path()),
+        ]
+    );
+}
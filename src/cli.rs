@@ -1,4 +1,4 @@
-use clap::{Arg, ArgMatches, Command};
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
 
 pub fn get_matches() -> ArgMatches {
     Command::new("carbide")
@@ -11,6 +11,75 @@ pub fn get_matches() -> ArgMatches {
                         .short('c')
                         .help("Set configuration directory path"),
                 )
+                .arg(
+                    Arg::new("data-directory")
+                        .long("data-directory")
+                        .short('d')
+                        .help("Set data directory path"),
+                )
+                .arg(
+                    Arg::new("profile")
+                        .long("profile")
+                        .visible_alias("host")
+                        .help("Evaluate hosts/<profile>.lua instead of init.lua when it exists"),
+                )
+                .arg(
+                    Arg::new("shell")
+                        .long("shell")
+                        .short('s')
+                        .help("Set shell used to run script commands"),
+                )
+                .arg(
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .action(ArgAction::SetTrue)
+                        .help("Print the planned differences without applying them"),
+                )
+                .arg(
+                    Arg::new("no-rollback")
+                        .long("no-rollback")
+                        .action(ArgAction::SetTrue)
+                        .help("Leave completed steps in place when applying fails"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("adopt")
+                        .help("Overwrite managed files that were modified outside carbide"),
+                )
+                .arg(
+                    Arg::new("adopt")
+                        .long("adopt")
+                        .action(ArgAction::SetTrue)
+                        .help("Keep local changes to managed files that were modified outside carbide"),
+                ),
+        )
+        .subcommand(
+            Command::new("plan")
+                .about("Print the differences switch would apply")
+                .arg(
+                    Arg::new("config-directory")
+                        .long("config-directory")
+                        .short('c')
+                        .help("Set configuration directory path"),
+                )
+                .arg(
+                    Arg::new("profile")
+                        .long("profile")
+                        .visible_alias("host")
+                        .help("Evaluate hosts/<profile>.lua instead of init.lua when it exists"),
+                )
+                .arg(
+                    Arg::new("data-directory")
+                        .long("data-directory")
+                        .short('d')
+                        .help("Set data directory path"),
+                ),
+        )
+        .subcommand(
+            Command::new("check")
+                .about("Compare the filesystem against the active generation")
                 .arg(
                     Arg::new("data-directory")
                         .long("data-directory")
@@ -30,10 +99,59 @@ pub fn get_matches() -> ArgMatches {
                                 .help("Set data directory path"),
                         ),
                 )
+                .subcommand(
+                    Command::new("rollback")
+                        .about("Re-apply a previous generation")
+                        .arg(
+                            Arg::new("generation-id")
+                                .help("Generation ID, defaults to the previous generation"),
+                        )
+                        .arg(
+                            Arg::new("config-directory")
+                                .long("config-directory")
+                                .short('c')
+                                .help("Set configuration directory path"),
+                        )
+                        .arg(
+                            Arg::new("data-directory")
+                                .long("data-directory")
+                                .short('d')
+                                .help("Set data directory path"),
+                        )
+                        .arg(
+                            Arg::new("shell")
+                                .long("shell")
+                                .short('s')
+                                .help("Set shell used to run script commands"),
+                        )
+                        .arg(
+                            Arg::new("no-rollback")
+                                .long("no-rollback")
+                                .action(ArgAction::SetTrue)
+                                .help("Leave completed steps in place when applying fails"),
+                        )
+                        .arg(
+                            Arg::new("force")
+                                .long("force")
+                                .action(ArgAction::SetTrue)
+                                .conflicts_with("adopt")
+                                .help("Overwrite managed files that were modified outside carbide"),
+                        )
+                        .arg(
+                            Arg::new("adopt")
+                                .long("adopt")
+                                .action(ArgAction::SetTrue)
+                                .help("Keep local changes to managed files that were modified outside carbide"),
+                        ),
+                )
                 .subcommand(
                     Command::new("delete")
                         .about("Delete a generation")
-                        .arg(Arg::new("generation-id").help("Generation ID"))
+                        .arg(
+                            Arg::new("generation-id")
+                                .help("Generation ID")
+                                .required(true),
+                        )
                         .arg(
                             Arg::new("data-directory")
                                 .long("data-directory")
@@ -42,12 +160,36 @@ pub fn get_matches() -> ArgMatches {
                         ),
                 )
                 .subcommand(
-                    Command::new("clean").about("Deletes old generations").arg(
-                        Arg::new("data-directory")
-                            .long("data-directory")
-                            .short('d')
-                            .help("Set data directory path"),
-                    ),
+                    Command::new("clean")
+                        .about("Deletes old generations")
+                        .arg(
+                            Arg::new("data-directory")
+                                .long("data-directory")
+                                .short('d')
+                                .help("Set data directory path"),
+                        )
+                        .arg(
+                            Arg::new("keep-last")
+                                .long("keep-last")
+                                .value_parser(value_parser!(usize))
+                                .help("Keep the last N generations, 5 when no other rule is given"),
+                        )
+                        .arg(
+                            Arg::new("older-than")
+                                .long("older-than")
+                                .help("Only delete generations older than an age such as 30d"),
+                        )
+                        .arg(
+                            Arg::new("keep-since")
+                                .long("keep-since")
+                                .help("Keep generations created since a date such as 2024-11-20"),
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .action(ArgAction::SetTrue)
+                                .help("Print the generations that would be deleted"),
+                        ),
                 )
                 .subcommand_required(true),
         )
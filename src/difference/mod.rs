@@ -1,4 +1,12 @@
-use crate::generations;
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use similar::TextDiff;
+
+use crate::{generations, services, store};
 
 use self::models::Difference;
 
@@ -12,12 +20,55 @@ pub fn differ_generations(
 ) -> Difference {
     let mut actions: Vec<models::Action> = Vec::new();
 
+    let (mut package_install_actions, mut package_removal_actions): (Vec<_>, Vec<_>) =
+        differ_generation_packages(initial_generation, final_generation)
+            .into_iter()
+            .partition(|action| {
+                matches!(
+                    action,
+                    models::Action::Package(models::Package::Install { .. })
+                )
+            });
+    actions.append(&mut package_install_actions);
+
     let mut file_actions = differ_generation_files(initial_generation, final_generation);
+    let changed_paths = changed_paths(&file_actions);
+    let (mut service_removal_actions, mut service_actions) =
+        differ_generation_services(initial_generation, final_generation, &changed_paths);
+    let mut hook_actions = differ_generation_hooks(final_generation, &changed_paths);
+
+    // Services leaving management are stopped before their unit files are removed.
+    actions.append(&mut service_removal_actions);
+
+    let (mut directory_actions, mut directory_removal_actions): (Vec<_>, Vec<_>) =
+        differ_generation_directories(initial_generation, final_generation)
+            .into_iter()
+            .partition(|action| {
+                matches!(
+                    action,
+                    models::Action::Directory(
+                        models::Directory::Create { .. } | models::Directory::Update { .. }
+                    )
+                )
+            });
+    actions.append(&mut directory_actions);
+
     actions.append(&mut file_actions);
 
+    let mut link_actions = differ_generation_links(initial_generation, final_generation);
+    actions.append(&mut link_actions);
+
+    actions.append(&mut directory_removal_actions);
+
+    actions.append(&mut service_actions);
+
+    actions.append(&mut hook_actions);
+
     let mut script_actions = differ_generation_scripts(initial_generation, final_generation);
     actions.append(&mut script_actions);
 
+    actions.append(&mut package_removal_actions);
+
     Difference { actions }
 }
 
@@ -37,14 +88,17 @@ fn differ_generation_files(
 
             path_matched = true;
 
-            if initial_file.content == final_file.content {
+            if initial_file.hash == final_file.hash
+                && initial_file.permissions == final_file.permissions
+            {
                 break;
             }
 
-            if let Some(content) = &final_file.content {
+            if let Some(hash) = &final_file.hash {
                 actions.push(models::Action::File(models::File::Update {
                     path: final_file.path.clone(),
-                    content: content.to_string(),
+                    hash: hash.to_string(),
+                    permissions: final_file.permissions.clone(),
                 }))
             } else {
                 actions.push(models::Action::File(models::File::Delete {
@@ -59,10 +113,11 @@ fn differ_generation_files(
             continue;
         }
 
-        if let Some(content) = &final_file.content {
+        if let Some(hash) = &final_file.hash {
             actions.push(models::Action::File(models::File::Create {
                 path: final_file.path.clone(),
-                content: content.to_string(),
+                hash: hash.to_string(),
+                permissions: final_file.permissions.clone(),
             }))
         } else {
             actions.push(models::Action::File(models::File::Delete {
@@ -81,11 +136,11 @@ fn differ_generation_files(
             }
         }
 
-        if path_matched {
+        if path_matched || initial_file.hash.is_none() {
             continue;
         }
 
-        actions.push(models::Action::File(models::File::Delete {
+        actions.push(models::Action::File(models::File::Unmanage {
             path: initial_file.path.clone(),
         }))
     }
@@ -93,45 +148,527 @@ fn differ_generation_files(
     actions
 }
 
-fn differ_generation_scripts(
+fn differ_generation_links(
     initial_generation: &generations::models::Generation,
     final_generation: &generations::models::Generation,
 ) -> Vec<models::Action> {
     let mut actions: Vec<models::Action> = Vec::new();
 
-    for initial_script in &initial_generation.scripts {
-        let mut install_matched = false;
+    for initial_link in &initial_generation.links {
+        if final_generation
+            .links
+            .iter()
+            .any(|final_link| final_link.path == initial_link.path)
+        {
+            continue;
+        }
 
-        for final_script in &final_generation.scripts {
-            if final_script.install == initial_script.install {
-                install_matched = true;
-                break;
+        actions.push(models::Action::Link(models::Link::Remove {
+            path: initial_link.path.clone(),
+        }))
+    }
+
+    for final_link in &final_generation.links {
+        let initial_link = initial_generation
+            .links
+            .iter()
+            .find(|initial_link| initial_link.path == final_link.path);
+
+        match initial_link {
+            Some(initial_link) => {
+                if initial_link.target == final_link.target {
+                    continue;
+                }
+
+                actions.push(models::Action::Link(models::Link::Retarget {
+                    path: final_link.path.clone(),
+                    target: final_link.target.clone(),
+                }))
             }
+            None => actions.push(models::Action::Link(models::Link::Create {
+                path: final_link.path.clone(),
+                target: final_link.target.clone(),
+            })),
         }
+    }
+
+    actions
+}
 
-        if install_matched {
+fn differ_generation_directories(
+    initial_generation: &generations::models::Generation,
+    final_generation: &generations::models::Generation,
+) -> Vec<models::Action> {
+    let mut actions: Vec<models::Action> = Vec::new();
+
+    for final_directory in &final_generation.directories {
+        let initial_directory = initial_generation
+            .directories
+            .iter()
+            .find(|initial_directory| initial_directory.path == final_directory.path);
+
+        if initial_directory == Some(final_directory) {
             continue;
         }
 
-        actions.push(models::Action::Script(initial_script.uninstall.clone()))
+        let path = final_directory.path.clone();
+        let permissions = final_directory.permissions.clone();
+
+        actions.push(models::Action::Directory(
+            match (initial_directory, final_directory.present) {
+                (Some(initial_directory), true) if initial_directory.present => {
+                    models::Directory::Update { path, permissions }
+                }
+                (_, true) => models::Directory::Create { path, permissions },
+                (_, false) => models::Directory::Delete { path },
+            },
+        ))
     }
 
-    for final_script in &final_generation.scripts {
-        let mut install_matched = false;
+    for initial_directory in &initial_generation.directories {
+        if !initial_directory.present
+            || final_generation
+                .directories
+                .iter()
+                .any(|final_directory| final_directory.path == initial_directory.path)
+        {
+            continue;
+        }
 
-        for initial_script in &initial_generation.scripts {
-            if initial_script.install == final_script.install {
-                install_matched = true;
-                break;
+        actions.push(models::Action::Directory(models::Directory::Unmanage {
+            path: initial_directory.path.clone(),
+        }))
+    }
+
+    actions.sort_by_key(|action| match action {
+        models::Action::Directory(
+            models::Directory::Create { path, .. } | models::Directory::Update { path, .. },
+        ) => path.components().count() as isize,
+        models::Action::Directory(
+            models::Directory::Delete { path } | models::Directory::Unmanage { path },
+        ) => -(path.components().count() as isize),
+        _ => 0,
+    });
+
+    actions
+}
+
+fn differ_generation_packages(
+    initial_generation: &generations::models::Generation,
+    final_generation: &generations::models::Generation,
+) -> Vec<models::Action> {
+    let mut installs = BTreeMap::<&str, Vec<String>>::new();
+    let mut removals = BTreeMap::<&str, Vec<String>>::new();
+
+    for final_package in &final_generation.packages {
+        if !initial_generation.packages.contains(final_package) {
+            installs
+                .entry(&final_package.manager)
+                .or_default()
+                .push(final_package.name.clone());
+        }
+    }
+
+    for initial_package in &initial_generation.packages {
+        if !final_generation.packages.contains(initial_package) {
+            removals
+                .entry(&initial_package.manager)
+                .or_default()
+                .push(initial_package.name.clone());
+        }
+    }
+
+    let mut actions: Vec<models::Action> = Vec::new();
+
+    for (manager, names) in installs {
+        actions.push(models::Action::Package(models::Package::Install {
+            manager: manager.to_string(),
+            names,
+        }))
+    }
+
+    for (manager, names) in removals {
+        actions.push(models::Action::Package(models::Package::Remove {
+            manager: manager.to_string(),
+            names,
+        }))
+    }
+
+    actions
+}
+
+/// Returns the paths of every file the file actions touch.
+fn changed_paths(file_actions: &[models::Action]) -> Vec<PathBuf> {
+    file_actions
+        .iter()
+        .filter_map(|action| match action {
+            models::Action::File(
+                models::File::Create { path, .. }
+                | models::File::Update { path, .. }
+                | models::File::Delete { path }
+                | models::File::Unmanage { path },
+            ) => Some(path.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns the actions for services leaving management, and the actions for the remaining
+/// services, which run once the files at `changed_paths` are applied.
+fn differ_generation_services(
+    initial_generation: &generations::models::Generation,
+    final_generation: &generations::models::Generation,
+    changed_paths: &[PathBuf],
+) -> (Vec<models::Action>, Vec<models::Action>) {
+    let mut removal_actions: Vec<models::Action> = Vec::new();
+    let mut actions: Vec<models::Action> = Vec::new();
+
+    for initial_service in &initial_generation.services {
+        if final_generation
+            .services
+            .iter()
+            .any(|final_service| final_service.name == initial_service.name)
+        {
+            continue;
+        }
+
+        let name = initial_service.name.clone();
+
+        if initial_service.running == Some(true) {
+            removal_actions.push(models::Action::Service(models::Service::Stop {
+                name: name.clone(),
+            }));
+        }
+
+        if initial_service.enabled == Some(true) {
+            removal_actions.push(models::Action::Service(models::Service::Disable { name }));
+        }
+    }
+
+    if changed_paths
+        .iter()
+        .any(|path| path.starts_with(services::UNIT_DIRECTORY))
+    {
+        actions.push(models::Action::Service(models::Service::DaemonReload));
+    }
+
+    for final_service in &final_generation.services {
+        let initial_service = initial_generation
+            .services
+            .iter()
+            .find(|initial_service| initial_service.name == final_service.name);
+        let name = final_service.name.clone();
+
+        if let Some(enabled) = final_service.enabled {
+            if initial_service.and_then(|service| service.enabled) != Some(enabled) {
+                actions.push(models::Action::Service(match enabled {
+                    true => models::Service::Enable { name: name.clone() },
+                    false => models::Service::Disable { name: name.clone() },
+                }));
+            }
+        }
+
+        let mut started = false;
+
+        if let Some(running) = final_service.running {
+            if initial_service.and_then(|service| service.running) != Some(running) {
+                started = running;
+                actions.push(models::Action::Service(match running {
+                    true => models::Service::Start { name: name.clone() },
+                    false => models::Service::Stop { name: name.clone() },
+                }));
             }
         }
 
-        if install_matched {
+        // A unit that was just started already runs with its new files, and one meant to be
+        // stopped stays stopped.
+        if started || final_service.running == Some(false) {
             continue;
         }
 
-        actions.push(models::Action::Script(final_script.install.clone()))
+        let unit_path = services::unit_path(&final_service.name);
+        if changed_paths.iter().any(|path| {
+            *path == unit_path
+                || final_service
+                    .restart_on
+                    .iter()
+                    .any(|restart_path| path == restart_path)
+        }) {
+            // Units whose state is left alone are only restarted when they happen to run.
+            actions.push(models::Action::Service(match final_service.running {
+                Some(true) => models::Service::Restart { name },
+                _ => models::Service::TryRestart { name },
+            }));
+        }
+    }
+
+    (removal_actions, actions)
+}
+
+/// Returns each command of the hooks watching any of the `changed_paths` once, in the order
+/// the hooks were declared.
+fn differ_generation_hooks(
+    final_generation: &generations::models::Generation,
+    changed_paths: &[PathBuf],
+) -> Vec<models::Action> {
+    let mut commands: Vec<&String> = Vec::new();
+
+    for hook in &final_generation.hooks {
+        let triggered = hook.paths.iter().any(|hook_path| {
+            changed_paths
+                .iter()
+                .any(|changed_path| changed_path.starts_with(hook_path))
+        });
+
+        if !triggered {
+            continue;
+        }
+
+        for command in &hook.commands {
+            if !commands.contains(&command) {
+                commands.push(command);
+            }
+        }
+    }
+
+    commands
+        .into_iter()
+        .map(|command| {
+            models::Action::Hook(models::Hook {
+                command: command.clone(),
+            })
+        })
+        .collect()
+}
+
+fn differ_generation_scripts(
+    initial_generation: &generations::models::Generation,
+    final_generation: &generations::models::Generation,
+) -> Vec<models::Action> {
+    let mut actions: Vec<models::Action> = Vec::new();
+
+    for initial_script in &initial_generation.scripts {
+        if final_generation
+            .scripts
+            .iter()
+            .any(|final_script| final_script.is_same_script(initial_script))
+        {
+            continue;
+        }
+
+        actions.push(models::Action::Script(models::Script::Uninstall {
+            generation_id: initial_generation.id,
+            commands: initial_script.uninstall.clone(),
+        }))
+    }
+
+    for final_script in &final_generation.scripts {
+        let initial_script = initial_generation
+            .scripts
+            .iter()
+            .find(|initial_script| initial_script.is_same_script(final_script));
+
+        match initial_script {
+            Some(initial_script) => {
+                if initial_script == final_script || final_script.update.is_empty() {
+                    continue;
+                }
+
+                actions.push(models::Action::Script(models::Script::Update {
+                    generation_id: final_generation.id,
+                    commands: final_script.update.clone(),
+                }))
+            }
+            None => actions.push(models::Action::Script(models::Script::Install {
+                generation_id: final_generation.id,
+                commands: final_script.install.clone(),
+            })),
+        }
     }
 
     actions
 }
+
+/// Renders the planned actions of a difference, with a unified diff of every file action
+/// against the content stored in the initial generation. Contents are read from `blobs`, or
+/// from the store in `data_directory` when they are not held there.
+pub fn format_difference(
+    data_directory: &Path,
+    blobs: &store::models::Blobs,
+    initial_generation: &generations::models::Generation,
+    difference: &Difference,
+) -> io::Result<String> {
+    let mut output = String::new();
+
+    for action in &difference.actions {
+        match action {
+            models::Action::File(file) => {
+                let default_permissions = generations::models::Permissions::default();
+                let (method, path, hash, permissions) = match file {
+                    models::File::Create {
+                        path,
+                        hash,
+                        permissions,
+                    } => ("Create File", path, Some(hash), permissions),
+                    models::File::Update {
+                        path,
+                        hash,
+                        permissions,
+                    } => ("Update File", path, Some(hash), permissions),
+                    models::File::Delete { path } => {
+                        ("Delete File", path, None, &default_permissions)
+                    }
+                    models::File::Unmanage { path } => {
+                        ("Unmanage File", path, None, &default_permissions)
+                    }
+                };
+
+                let initial_file = initial_generation
+                    .files
+                    .iter()
+                    .find(|file| file.path == *path);
+                let initial_content = read_content(
+                    data_directory,
+                    blobs,
+                    initial_file.and_then(|file| file.hash.as_ref()),
+                )?;
+                let content = read_content(data_directory, blobs, hash)?;
+                let initial_permissions = initial_file
+                    .map(|file| &file.permissions)
+                    .unwrap_or(&default_permissions);
+
+                output.push_str(&format!("( {} ) {}\n", method, path.display()));
+                if initial_permissions != permissions {
+                    output.push_str(&format!(
+                        "( Permissions ) {} -> {}\n",
+                        format_permissions(initial_permissions),
+                        format_permissions(permissions)
+                    ));
+                }
+                output.push_str(&format_content_difference(path, &initial_content, &content));
+            }
+            models::Action::Directory(directory) => {
+                let (method, path) = match directory {
+                    models::Directory::Create { path, .. } => ("Create Directory", path),
+                    models::Directory::Update { path, .. } => ("Update Directory", path),
+                    models::Directory::Delete { path } => ("Delete Directory", path),
+                    models::Directory::Unmanage { path } => ("Unmanage Directory", path),
+                };
+
+                output.push_str(&format!("( {} ) {}\n", method, path.display()));
+            }
+            models::Action::Link(link) => match link {
+                models::Link::Create { path, target } => output.push_str(&format!(
+                    "( Create Link ) {} -> {}\n",
+                    path.display(),
+                    target.display()
+                )),
+                models::Link::Retarget { path, target } => output.push_str(&format!(
+                    "( Retarget Link ) {} -> {}\n",
+                    path.display(),
+                    target.display()
+                )),
+                models::Link::Remove { path } => {
+                    output.push_str(&format!("( Remove Link ) {}\n", path.display()))
+                }
+            },
+            models::Action::Package(package) => {
+                let (method, manager, names) = match package {
+                    models::Package::Install { manager, names } => {
+                        ("Install Packages", manager, names)
+                    }
+                    models::Package::Remove { manager, names } => {
+                        ("Remove Packages", manager, names)
+                    }
+                };
+
+                output.push_str(&format!(
+                    "( {} ) {}: {}\n",
+                    method,
+                    manager,
+                    names.join(" ")
+                ));
+            }
+            models::Action::Service(service) => {
+                output.push_str(&format!("{}\n", format_service(service)));
+            }
+            models::Action::Hook(hook) => {
+                output.push_str(&format!("( Run Hook ) {}\n", hook.command));
+            }
+            models::Action::Script(script) => {
+                let method = match script {
+                    models::Script::Install { .. } => "Install Script",
+                    models::Script::Update { .. } => "Update Script",
+                    models::Script::Uninstall { .. } => "Uninstall Script",
+                };
+
+                output.push_str(&format!(
+                    "( {} ) generation {}\n",
+                    method,
+                    script.generation_id()
+                ));
+                for command in script.commands() {
+                    output.push_str(&format!("$ {}\n", command));
+                }
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+fn read_content(
+    data_directory: &Path,
+    blobs: &store::models::Blobs,
+    hash: Option<&String>,
+) -> io::Result<Vec<u8>> {
+    match hash {
+        Some(hash) => blobs.read(data_directory, hash),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Non-UTF-8 contents are only reported as changed, like `diff` does for binary files.
+fn format_content_difference(path: &Path, initial_content: &[u8], final_content: &[u8]) -> String {
+    let path = path.display().to_string();
+
+    match (
+        std::str::from_utf8(initial_content),
+        std::str::from_utf8(final_content),
+    ) {
+        (Ok(initial_content), Ok(final_content)) => {
+            TextDiff::from_lines(initial_content, final_content)
+                .unified_diff()
+                .header(&path, &path)
+                .to_string()
+        }
+        _ if initial_content == final_content => String::new(),
+        _ => format!("Binary files {} and {} differ\n", path, path),
+    }
+}
+
+fn format_permissions(permissions: &generations::models::Permissions) -> String {
+    format!(
+        "mode={} owner={} group={}",
+        permissions
+            .mode
+            .map(|mode| format!("{:04o}", mode))
+            .unwrap_or_else(|| String::from("-")),
+        permissions.owner.as_deref().unwrap_or("-"),
+        permissions.group.as_deref().unwrap_or("-")
+    )
+}
+
+pub fn format_service(service: &models::Service) -> String {
+    let (method, name) = match service {
+        models::Service::DaemonReload => return String::from("( Reload Units )"),
+        models::Service::Enable { name } => ("Enable Service", name),
+        models::Service::Disable { name } => ("Disable Service", name),
+        models::Service::Start { name } => ("Start Service", name),
+        models::Service::Stop { name } => ("Stop Service", name),
+        models::Service::Restart { name } => ("Restart Service", name),
+        models::Service::TryRestart { name } => ("Restart Service If Running", name),
+    };
+
+    format!("( {} ) {}", method, name)
+}
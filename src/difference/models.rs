@@ -1,5 +1,7 @@
 use std::path::PathBuf;
 
+use crate::generations;
+
 #[derive(Debug, PartialEq)]
 pub struct Difference {
     pub actions: Vec<Action>,
@@ -8,14 +10,128 @@ pub struct Difference {
 #[derive(Debug, PartialEq)]
 pub enum Action {
     File(File),
+    Directory(Directory),
+    Link(Link),
+    Package(Package),
+    Service(Service),
+    Hook(Hook),
     Script(Script),
 }
 
 #[derive(Debug, PartialEq)]
 pub enum File {
-    Create { path: PathBuf, content: String },
-    Update { path: PathBuf, content: String },
+    Create {
+        path: PathBuf,
+        /// Hash of the content in the store.
+        hash: String,
+        permissions: generations::models::Permissions,
+    },
+    Update {
+        path: PathBuf,
+        /// Hash of the content in the store.
+        hash: String,
+        permissions: generations::models::Permissions,
+    },
+    Delete {
+        path: PathBuf,
+    },
+    /// Stops managing the file, restoring the original it replaced or removing it otherwise.
+    Unmanage {
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Link {
+    Create { path: PathBuf, target: PathBuf },
+    Retarget { path: PathBuf, target: PathBuf },
+    Remove { path: PathBuf },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Directory {
+    Create {
+        path: PathBuf,
+        permissions: generations::models::Permissions,
+    },
+    Update {
+        path: PathBuf,
+        permissions: generations::models::Permissions,
+    },
+    /// Removes the directory and everything in it.
     Delete { path: PathBuf },
+    /// Stops managing the directory, removing it only if carbide created it and it is empty.
+    Unmanage { path: PathBuf },
 }
 
-pub type Script = Vec<String>;
+/// Packages are batched per package manager so that each batch is a single transaction.
+#[derive(Debug, PartialEq)]
+pub enum Package {
+    Install { manager: String, names: Vec<String> },
+    Remove { manager: String, names: Vec<String> },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Service {
+    /// Reloads unit files after they changed.
+    DaemonReload,
+    Enable {
+        name: String,
+    },
+    Disable {
+        name: String,
+    },
+    Start {
+        name: String,
+    },
+    Stop {
+        name: String,
+    },
+    Restart {
+        name: String,
+    },
+    /// Restarts the unit only if it is running.
+    TryRestart {
+        name: String,
+    },
+}
+
+/// A command triggered by changes to the files a hook watches, run once per difference.
+#[derive(Debug, PartialEq)]
+pub struct Hook {
+    pub command: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Script {
+    Install {
+        generation_id: i32,
+        commands: Vec<String>,
+    },
+    Update {
+        generation_id: i32,
+        commands: Vec<String>,
+    },
+    Uninstall {
+        generation_id: i32,
+        commands: Vec<String>,
+    },
+}
+
+impl Script {
+    pub fn generation_id(&self) -> i32 {
+        match self {
+            Script::Install { generation_id, .. }
+            | Script::Update { generation_id, .. }
+            | Script::Uninstall { generation_id, .. } => *generation_id,
+        }
+    }
+
+    pub fn commands(&self) -> &[String] {
+        match self {
+            Script::Install { commands, .. }
+            | Script::Update { commands, .. }
+            | Script::Uninstall { commands, .. } => commands,
+        }
+    }
+}
@@ -1,55 +1,57 @@
 use std::path::PathBuf;
 
-use chrono::Local;
-
 use crate::{
     difference::{
         self,
-        models::{Action, Difference, File},
+        models::{Action, Difference, Directory, File, Hook, Link, Package, Script, Service},
     },
-    generations,
+    generations, store,
 };
 
 #[test]
 fn differ_generations_files() {
     let initial_generation = generations::models::Generation {
         id: 0,
-        creation_datetime: Local::now(),
         files: vec![
             generations::models::File {
                 path: PathBuf::from("set_and_"),
-                content: Some(String::from("Hello World")),
+                hash: Some(store::hash(b"Hello World")),
+                permissions: generations::models::Permissions::default(),
             },
             generations::models::File {
                 path: PathBuf::from("set_and_delete"),
-                content: Some(String::from("Hello World")),
+                hash: Some(store::hash(b"Hello World")),
+                permissions: generations::models::Permissions::default(),
             },
             generations::models::File {
                 path: PathBuf::from("set_and_update"),
-                content: Some(String::from("Initial Content")),
+                hash: Some(store::hash(b"Initial Content")),
+                permissions: generations::models::Permissions::default(),
             },
         ],
-        scripts: vec![],
+        ..generations::models::Generation::new()
     };
 
     let final_generation = generations::models::Generation {
         id: 1,
-        creation_datetime: Local::now(),
         files: vec![
             generations::models::File {
                 path: PathBuf::from("set_and_delete"),
-                content: None,
+                hash: None,
+                permissions: generations::models::Permissions::default(),
             },
             generations::models::File {
                 path: PathBuf::from("set_and_update"),
-                content: Some(String::from("Final Content")),
+                hash: Some(store::hash(b"Final Content")),
+                permissions: generations::models::Permissions::default(),
             },
             generations::models::File {
                 path: PathBuf::from("_and_create"),
-                content: Some(String::from("New Content")),
+                hash: Some(store::hash(b"New Content")),
+                permissions: generations::models::Permissions::default(),
             },
         ],
-        scripts: vec![],
+        ..generations::models::Generation::new()
     };
 
     assert_eq!(
@@ -61,13 +63,15 @@ fn differ_generations_files() {
                 }),
                 Action::File(File::Update {
                     path: PathBuf::from("set_and_update"),
-                    content: String::from("Final Content")
+                    hash: store::hash(b"Final Content"),
+                    permissions: generations::models::Permissions::default(),
                 }),
                 Action::File(File::Create {
                     path: PathBuf::from("_and_create"),
-                    content: String::from("New Content")
+                    hash: store::hash(b"New Content"),
+                    permissions: generations::models::Permissions::default(),
                 }),
-                Action::File(File::Delete {
+                Action::File(File::Unmanage {
                     path: PathBuf::from("set_and_"),
                 })
             ]
@@ -75,45 +79,627 @@ fn differ_generations_files() {
     )
 }
 
+#[test]
+fn differ_generations_file_permissions() {
+    let initial_generation = generations::models::Generation {
+        id: 0,
+        files: vec![generations::models::File {
+            path: PathBuf::from("/etc/ssh/sshd_config"),
+            hash: Some(store::hash(b"PermitRootLogin no")),
+            permissions: generations::models::Permissions::default(),
+        }],
+        ..generations::models::Generation::new()
+    };
+
+    let permissions = generations::models::Permissions {
+        mode: Some(0o600),
+        owner: Some(String::from("root")),
+        group: None,
+    };
+
+    let final_generation = generations::models::Generation {
+        id: 1,
+        files: vec![generations::models::File {
+            path: PathBuf::from("/etc/ssh/sshd_config"),
+            hash: Some(store::hash(b"PermitRootLogin no")),
+            permissions: permissions.clone(),
+        }],
+        ..generations::models::Generation::new()
+    };
+
+    assert_eq!(
+        difference::differ_generations(&initial_generation, &final_generation),
+        Difference {
+            actions: vec![Action::File(File::Update {
+                path: PathBuf::from("/etc/ssh/sshd_config"),
+                hash: store::hash(b"PermitRootLogin no"),
+                permissions,
+            })]
+        }
+    )
+}
+
+#[test]
+fn differ_generations_directories() {
+    let initial_generation = generations::models::Generation {
+        id: 0,
+        directories: vec![
+            generations::models::Directory {
+                path: PathBuf::from("/etc/nginx"),
+                present: true,
+                permissions: generations::models::Permissions::default(),
+            },
+            generations::models::Directory {
+                path: PathBuf::from("/etc/nginx/sites"),
+                present: true,
+                permissions: generations::models::Permissions::default(),
+            },
+            generations::models::Directory {
+                path: PathBuf::from("/etc/ssh"),
+                present: true,
+                permissions: generations::models::Permissions::default(),
+            },
+        ],
+        ..generations::models::Generation::new()
+    };
+
+    let final_generation = generations::models::Generation {
+        id: 1,
+        files: vec![generations::models::File {
+            path: PathBuf::from("/etc/neovim/init.lua"),
+            hash: Some(store::hash(b"Hello World")),
+            permissions: generations::models::Permissions::default(),
+        }],
+        directories: vec![
+            generations::models::Directory {
+                path: PathBuf::from("/etc/ssh"),
+                present: true,
+                permissions: generations::models::Permissions {
+                    mode: Some(0o700),
+                    owner: None,
+                    group: None,
+                },
+            },
+            generations::models::Directory {
+                path: PathBuf::from("/etc/neovim/lua"),
+                present: true,
+                permissions: generations::models::Permissions::default(),
+            },
+            generations::models::Directory {
+                path: PathBuf::from("/etc/neovim"),
+                present: true,
+                permissions: generations::models::Permissions::default(),
+            },
+            generations::models::Directory {
+                path: PathBuf::from("/var/cache/carbide"),
+                present: false,
+                permissions: generations::models::Permissions::default(),
+            },
+        ],
+        ..generations::models::Generation::new()
+    };
+
+    assert_eq!(
+        difference::differ_generations(&initial_generation, &final_generation),
+        Difference {
+            actions: vec![
+                Action::Directory(Directory::Update {
+                    path: PathBuf::from("/etc/ssh"),
+                    permissions: generations::models::Permissions {
+                        mode: Some(0o700),
+                        owner: None,
+                        group: None,
+                    },
+                }),
+                Action::Directory(Directory::Create {
+                    path: PathBuf::from("/etc/neovim"),
+                    permissions: generations::models::Permissions::default(),
+                }),
+                Action::Directory(Directory::Create {
+                    path: PathBuf::from("/etc/neovim/lua"),
+                    permissions: generations::models::Permissions::default(),
+                }),
+                Action::File(File::Create {
+                    path: PathBuf::from("/etc/neovim/init.lua"),
+                    hash: store::hash(b"Hello World"),
+                    permissions: generations::models::Permissions::default(),
+                }),
+                Action::Directory(Directory::Delete {
+                    path: PathBuf::from("/var/cache/carbide"),
+                }),
+                Action::Directory(Directory::Unmanage {
+                    path: PathBuf::from("/etc/nginx/sites"),
+                }),
+                Action::Directory(Directory::Unmanage {
+                    path: PathBuf::from("/etc/nginx"),
+                }),
+            ]
+        }
+    )
+}
+
+#[test]
+fn differ_generations_links() {
+    let initial_generation = generations::models::Generation {
+        id: 0,
+        links: vec![
+            generations::models::Link {
+                target: PathBuf::from("/home/carbide/dotfiles/nvim"),
+                path: PathBuf::from("/home/carbide/.config/nvim"),
+            },
+            generations::models::Link {
+                target: PathBuf::from("/home/carbide/dotfiles/bashrc"),
+                path: PathBuf::from("/home/carbide/.bashrc"),
+            },
+            generations::models::Link {
+                target: PathBuf::from("/home/carbide/dotfiles/zshrc"),
+                path: PathBuf::from("/home/carbide/.zshrc"),
+            },
+        ],
+        ..generations::models::Generation::new()
+    };
+
+    let final_generation = generations::models::Generation {
+        id: 1,
+        links: vec![
+            generations::models::Link {
+                target: PathBuf::from("/home/carbide/dotfiles/nvim"),
+                path: PathBuf::from("/home/carbide/.config/nvim"),
+            },
+            generations::models::Link {
+                target: PathBuf::from("/home/carbide/dotfiles/bashrc_2"),
+                path: PathBuf::from("/home/carbide/.bashrc"),
+            },
+            generations::models::Link {
+                target: PathBuf::from("/home/carbide/dotfiles/gitconfig"),
+                path: PathBuf::from("/home/carbide/.gitconfig"),
+            },
+        ],
+        ..generations::models::Generation::new()
+    };
+
+    assert_eq!(
+        difference::differ_generations(&initial_generation, &final_generation),
+        Difference {
+            actions: vec![
+                Action::Link(Link::Remove {
+                    path: PathBuf::from("/home/carbide/.zshrc"),
+                }),
+                Action::Link(Link::Retarget {
+                    path: PathBuf::from("/home/carbide/.bashrc"),
+                    target: PathBuf::from("/home/carbide/dotfiles/bashrc_2"),
+                }),
+                Action::Link(Link::Create {
+                    path: PathBuf::from("/home/carbide/.gitconfig"),
+                    target: PathBuf::from("/home/carbide/dotfiles/gitconfig"),
+                }),
+            ]
+        }
+    )
+}
+
 #[test]
 fn differ_generations_scripts() {
     let initial_generation = generations::models::Generation {
         id: 0,
-        creation_datetime: Local::now(),
-        files: vec![],
         scripts: vec![
             generations::models::Script {
+                name: None,
                 install: vec![String::from("sudo apt-get install ffmpeg")],
                 update: vec![String::from("sudo apt-get update ffmpeg")],
                 uninstall: vec![String::from("sudo apt-get uninstall ffmpeg")],
             },
             generations::models::Script {
+                name: None,
                 install: vec![String::from("sudo apt-get install docker")],
                 update: vec![String::from("sudo apt-get update docker")],
                 uninstall: vec![String::from("sudo apt-get uninstall docker")],
             },
         ],
+        ..generations::models::Generation::new()
     };
 
     let final_generation = generations::models::Generation {
         id: 1,
-        creation_datetime: Local::now(),
-        files: vec![],
         scripts: vec![generations::models::Script {
+            name: None,
             install: vec![String::from("sudo apt-get install ffmpeg_2")],
             update: vec![String::from("sudo apt-get update ffmpeg")],
             uninstall: vec![String::from("sudo apt-get uninstall ffmpeg")],
         }],
+        ..generations::models::Generation::new()
     };
 
     assert_eq!(
         difference::differ_generations(&initial_generation, &final_generation),
         Difference {
             actions: vec![
-                Action::Script(vec![String::from("sudo apt-get uninstall ffmpeg"),]),
-                Action::Script(vec![String::from("sudo apt-get uninstall docker")]),
-                Action::Script(vec![String::from("sudo apt-get install ffmpeg_2"),]),
+                Action::Script(Script::Uninstall {
+                    generation_id: 0,
+                    commands: vec![String::from("sudo apt-get uninstall ffmpeg")],
+                }),
+                Action::Script(Script::Uninstall {
+                    generation_id: 0,
+                    commands: vec![String::from("sudo apt-get uninstall docker")],
+                }),
+                Action::Script(Script::Install {
+                    generation_id: 1,
+                    commands: vec![String::from("sudo apt-get install ffmpeg_2")],
+                }),
             ]
         }
     )
 }
+
+#[test]
+fn differ_generations_scripts_update() {
+    let initial_generation = generations::models::Generation {
+        id: 0,
+        scripts: vec![
+            generations::models::Script {
+                name: Some(String::from("ffmpeg")),
+                install: vec![String::from("sudo apt-get install ffmpeg")],
+                update: vec![String::from("sudo apt-get update ffmpeg")],
+                uninstall: vec![String::from("sudo apt-get uninstall ffmpeg")],
+            },
+            generations::models::Script {
+                name: None,
+                install: vec![String::from("sudo apt-get install docker")],
+                update: vec![String::from("sudo apt-get update docker")],
+                uninstall: vec![String::from("sudo apt-get uninstall docker")],
+            },
+            generations::models::Script {
+                name: Some(String::from("neovim")),
+                install: vec![String::from("sudo apt-get install neovim")],
+                update: vec![String::from("sudo apt-get update neovim")],
+                uninstall: vec![String::from("sudo apt-get uninstall neovim")],
+            },
+        ],
+        ..generations::models::Generation::new()
+    };
+
+    let final_generation = generations::models::Generation {
+        id: 1,
+        scripts: vec![
+            generations::models::Script {
+                name: Some(String::from("ffmpeg")),
+                install: vec![String::from("sudo apt-get install ffmpeg_2")],
+                update: vec![String::from("sudo apt-get update ffmpeg_2")],
+                uninstall: vec![String::from("sudo apt-get uninstall ffmpeg_2")],
+            },
+            generations::models::Script {
+                name: None,
+                install: vec![String::from("sudo apt-get install docker")],
+                update: vec![String::from("sudo apt-get upgrade docker")],
+                uninstall: vec![String::from("sudo apt-get uninstall docker")],
+            },
+            generations::models::Script {
+                name: Some(String::from("neovim")),
+                install: vec![String::from("sudo apt-get install neovim")],
+                update: vec![String::from("sudo apt-get update neovim")],
+                uninstall: vec![String::from("sudo apt-get uninstall neovim")],
+            },
+        ],
+        ..generations::models::Generation::new()
+    };
+
+    assert_eq!(
+        difference::differ_generations(&initial_generation, &final_generation),
+        Difference {
+            actions: vec![
+                Action::Script(Script::Update {
+                    generation_id: 1,
+                    commands: vec![String::from("sudo apt-get update ffmpeg_2")],
+                }),
+                Action::Script(Script::Update {
+                    generation_id: 1,
+                    commands: vec![String::from("sudo apt-get upgrade docker")],
+                }),
+            ]
+        }
+    )
+}
+
+#[test]
+fn differ_generations_packages() {
+    let package = |name: &str, manager: &str| generations::models::Package {
+        name: String::from(name),
+        manager: String::from(manager),
+    };
+
+    let initial_generation = generations::models::Generation {
+        id: 0,
+        packages: vec![
+            package("neovim", "apt"),
+            package("docker", "apt"),
+            package("ffmpeg", "apt"),
+        ],
+        ..generations::models::Generation::new()
+    };
+
+    let final_generation = generations::models::Generation {
+        id: 1,
+        files: vec![generations::models::File {
+            path: PathBuf::from("/etc/motd"),
+            hash: Some(store::hash(b"Hello World")),
+            permissions: generations::models::Permissions::default(),
+        }],
+        packages: vec![
+            package("neovim", "apt"),
+            package("ripgrep", "pacman"),
+            package("git", "apt"),
+            package("fd", "pacman"),
+        ],
+        ..generations::models::Generation::new()
+    };
+
+    assert_eq!(
+        difference::differ_generations(&initial_generation, &final_generation),
+        Difference {
+            actions: vec![
+                Action::Package(Package::Install {
+                    manager: String::from("apt"),
+                    names: vec![String::from("git")],
+                }),
+                Action::Package(Package::Install {
+                    manager: String::from("pacman"),
+                    names: vec![String::from("ripgrep"), String::from("fd")],
+                }),
+                Action::File(File::Create {
+                    path: PathBuf::from("/etc/motd"),
+                    hash: store::hash(b"Hello World"),
+                    permissions: generations::models::Permissions::default(),
+                }),
+                Action::Package(Package::Remove {
+                    manager: String::from("apt"),
+                    names: vec![String::from("docker"), String::from("ffmpeg")],
+                }),
+            ]
+        }
+    )
+}
+
+#[test]
+fn differ_generations_services() {
+    let unit = |name: &str, content: &[u8]| generations::models::File {
+        path: PathBuf::from(format!("/etc/systemd/system/{}", name)),
+        hash: Some(store::hash(content)),
+        permissions: generations::models::Permissions::default(),
+    };
+    let service = |name: &str, enabled: bool, running: bool| generations::models::Service {
+        name: String::from(name),
+        enabled: Some(enabled),
+        running: Some(running),
+        restart_on: vec![PathBuf::from("/etc/nginx/nginx.conf")],
+    };
+    // Its state is left alone, so it is only restarted if it runs.
+    let unmanaged_state_service = generations::models::Service {
+        name: String::from("php-fpm.service"),
+        enabled: None,
+        running: None,
+        restart_on: vec![PathBuf::from("/etc/nginx/nginx.conf")],
+    };
+
+    let initial_generation = generations::models::Generation {
+        id: 0,
+        files: vec![
+            unit("nginx.service", b"[Service]"),
+            unit("old.service", b"[Service]"),
+            generations::models::File {
+                path: PathBuf::from("/etc/nginx/nginx.conf"),
+                hash: Some(store::hash(b"worker_processes 1;")),
+                permissions: generations::models::Permissions::default(),
+            },
+        ],
+        services: vec![
+            service("nginx.service", true, true),
+            service("old.service", true, true),
+            unmanaged_state_service.clone(),
+        ],
+        ..generations::models::Generation::new()
+    };
+
+    let final_generation = generations::models::Generation {
+        id: 1,
+        files: vec![
+            unit("nginx.service", b"[Service]"),
+            unit("new.service", b"[Service]"),
+            generations::models::File {
+                path: PathBuf::from("/etc/nginx/nginx.conf"),
+                hash: Some(store::hash(b"worker_processes 4;")),
+                permissions: generations::models::Permissions::default(),
+            },
+        ],
+        services: vec![
+            service("nginx.service", true, true),
+            service("new.service", false, true),
+            unmanaged_state_service,
+        ],
+        ..generations::models::Generation::new()
+    };
+
+    assert_eq!(
+        difference::differ_generations(&initial_generation, &final_generation),
+        Difference {
+            actions: vec![
+                Action::Service(Service::Stop {
+                    name: String::from("old.service")
+                }),
+                Action::Service(Service::Disable {
+                    name: String::from("old.service")
+                }),
+                Action::File(File::Create {
+                    path: PathBuf::from("/etc/systemd/system/new.service"),
+                    hash: store::hash(b"[Service]"),
+                    permissions: generations::models::Permissions::default(),
+                }),
+                Action::File(File::Update {
+                    path: PathBuf::from("/etc/nginx/nginx.conf"),
+                    hash: store::hash(b"worker_processes 4;"),
+                    permissions: generations::models::Permissions::default(),
+                }),
+                Action::File(File::Unmanage {
+                    path: PathBuf::from("/etc/systemd/system/old.service")
+                }),
+                Action::Service(Service::DaemonReload),
+                Action::Service(Service::Restart {
+                    name: String::from("nginx.service")
+                }),
+                Action::Service(Service::Disable {
+                    name: String::from("new.service")
+                }),
+                Action::Service(Service::Start {
+                    name: String::from("new.service")
+                }),
+                Action::Service(Service::TryRestart {
+                    name: String::from("php-fpm.service")
+                }),
+            ]
+        }
+    )
+}
+
+#[test]
+fn differ_generations_hooks() {
+    let file = |path: &str, content: &[u8]| generations::models::File {
+        path: PathBuf::from(path),
+        hash: Some(store::hash(content)),
+        permissions: generations::models::Permissions::default(),
+    };
+    let hook = |paths: &[&str], commands: &[&str]| generations::models::Hook {
+        paths: paths.iter().map(PathBuf::from).collect(),
+        commands: commands.iter().map(|command| command.to_string()).collect(),
+    };
+
+    let initial_generation = generations::models::Generation {
+        id: 0,
+        files: vec![
+            file("/etc/nginx/nginx.conf", b"worker_processes 1;"),
+            file("/etc/nginx/sites/default", b"server {}"),
+            file("/etc/motd", b"Hello World"),
+        ],
+        ..generations::models::Generation::new()
+    };
+
+    let final_generation = generations::models::Generation {
+        id: 1,
+        files: vec![
+            file("/etc/nginx/nginx.conf", b"worker_processes 4;"),
+            file("/etc/nginx/sites/default", b"server { listen 80; }"),
+            file("/etc/motd", b"Hello World"),
+        ],
+        hooks: vec![
+            hook(
+                &["/etc/nginx/nginx.conf"],
+                &["nginx -t", "systemctl reload nginx"],
+            ),
+            hook(&["/etc/nginx/sites"], &["systemctl reload nginx"]),
+            hook(&["/etc/motd"], &["echo motd"]),
+        ],
+        ..generations::models::Generation::new()
+    };
+
+    assert_eq!(
+        difference::differ_generations(&initial_generation, &final_generation),
+        Difference {
+            actions: vec![
+                Action::File(File::Update {
+                    path: PathBuf::from("/etc/nginx/nginx.conf"),
+                    hash: store::hash(b"worker_processes 4;"),
+                    permissions: generations::models::Permissions::default(),
+                }),
+                Action::File(File::Update {
+                    path: PathBuf::from("/etc/nginx/sites/default"),
+                    hash: store::hash(b"server { listen 80; }"),
+                    permissions: generations::models::Permissions::default(),
+                }),
+                Action::Hook(Hook {
+                    command: String::from("nginx -t"),
+                }),
+                Action::Hook(Hook {
+                    command: String::from("systemctl reload nginx"),
+                }),
+            ]
+        }
+    )
+}
+
+#[test]
+fn format_difference() {
+    let data_directory = assert_fs::TempDir::new().unwrap();
+    let initial_hash =
+        store::write_blob(data_directory.path(), b"Hello World\nSecond Line\n").unwrap();
+    let mut blobs = store::models::Blobs::default();
+    let final_hash = blobs.add(b"Hello World\nChanged Line\n".to_vec());
+
+    let initial_generation = generations::models::Generation {
+        id: 0,
+        files: vec![generations::models::File {
+            path: PathBuf::from("/etc/neovim"),
+            hash: Some(initial_hash),
+            permissions: generations::models::Permissions::default(),
+        }],
+        ..generations::models::Generation::new()
+    };
+
+    let difference = Difference {
+        actions: vec![
+            Action::File(File::Update {
+                path: PathBuf::from("/etc/neovim"),
+                hash: final_hash,
+                permissions: generations::models::Permissions::default(),
+            }),
+            Action::Script(Script::Install {
+                generation_id: 1,
+                commands: vec![String::from("sudo apt-get install neovim")],
+            }),
+        ],
+    };
+
+    assert_eq!(
+        difference::format_difference(
+            data_directory.path(),
+            &blobs,
+            &initial_generation,
+            &difference
+        )
+        .unwrap(),
+        "( Update File ) /etc/neovim
+--- /etc/neovim
++++ /etc/neovim
+@@ -1,2 +1,2 @@
+ Hello World
+-Second Line
++Changed Line
+( Install Script ) generation 1
+$ sudo apt-get install neovim
+"
+    )
+}
+
+#[test]
+fn format_difference_binary() {
+    let data_directory = assert_fs::TempDir::new().unwrap();
+    let hash = store::write_blob(data_directory.path(), &[0x99, 0x00, 0xff]).unwrap();
+
+    let difference = Difference {
+        actions: vec![Action::File(File::Create {
+            path: PathBuf::from("/usr/share/keyrings/carbide.gpg"),
+            hash,
+            permissions: generations::models::Permissions::default(),
+        })],
+    };
+
+    assert_eq!(
+        difference::format_difference(
+            data_directory.path(),
+            &store::models::Blobs::default(),
+            &generations::models::Generation::new(),
+            &difference
+        )
+        .unwrap(),
+        "( Create File ) /usr/share/keyrings/carbide.gpg
+Binary files /usr/share/keyrings/carbide.gpg and /usr/share/keyrings/carbide.gpg differ
+"
+    )
+}
@@ -0,0 +1,236 @@
+use std::{fs, io, os::unix::fs::MetadataExt, path::Path};
+
+use crate::{apply, difference, generations, store};
+
+pub mod models;
+#[cfg(test)]
+mod tests;
+
+/// Compares the live filesystem with every file, directory and link of a generation.
+pub fn check_generation(
+    generation: &generations::models::Generation,
+) -> io::Result<Vec<models::Drift>> {
+    let mut drifts = Vec::new();
+
+    for file in &generation.files {
+        if let Some(drift) = check_file(file)? {
+            drifts.push(drift);
+        }
+    }
+
+    for directory in &generation.directories {
+        if let Some(drift) = check_directory(directory)? {
+            drifts.push(drift);
+        }
+    }
+
+    for link in &generation.links {
+        if let Some(drift) = check_link(link)? {
+            drifts.push(drift);
+        }
+    }
+
+    Ok(drifts)
+}
+
+/// Returns the drift of every file the difference is about to change, compared with what the
+/// initial generation put there.
+pub fn check_difference(
+    initial_generation: &generations::models::Generation,
+    difference: &difference::models::Difference,
+) -> io::Result<Vec<models::Drift>> {
+    let mut drifts = Vec::new();
+
+    for action in &difference.actions {
+        // Files about to be deleted or unmanaged may already be gone without harm.
+        let (path, removed) = match action {
+            difference::models::Action::File(difference::models::File::Update { path, .. }) => {
+                (path, false)
+            }
+            difference::models::Action::File(
+                difference::models::File::Delete { path }
+                | difference::models::File::Unmanage { path },
+            ) => (path, true),
+            _ => continue,
+        };
+
+        let initial_file = initial_generation
+            .files
+            .iter()
+            .find(|file| file.path == *path && file.hash.is_some());
+
+        if let Some(initial_file) = initial_file {
+            match check_file(initial_file)? {
+                Some(models::Drift::Missing { .. }) if removed => {}
+                Some(drift) => drifts.push(drift),
+                None => {}
+            }
+        }
+    }
+
+    Ok(drifts)
+}
+
+/// Keeps local changes to drifted files: their file actions are dropped from the difference
+/// and their live content is added to the store in `data_directory` and recorded in the
+/// generation in place of the configured one.
+pub fn adopt_drifts(
+    data_directory: &Path,
+    drifts: &[models::Drift],
+    generation: &mut generations::models::Generation,
+    difference: &mut difference::models::Difference,
+) -> io::Result<()> {
+    for drift in drifts {
+        let path = drift.path();
+
+        difference.actions.retain(|action| match action {
+            difference::models::Action::File(
+                difference::models::File::Create {
+                    path: action_path, ..
+                }
+                | difference::models::File::Update {
+                    path: action_path, ..
+                }
+                | difference::models::File::Delete { path: action_path }
+                | difference::models::File::Unmanage { path: action_path },
+            ) => action_path != path,
+            _ => true,
+        });
+
+        // Files that leave management are simply left as they are.
+        let Some(file) = generation.files.iter_mut().find(|file| file.path == path) else {
+            continue;
+        };
+
+        file.hash = match fs::read(path) {
+            Ok(content) => Some(store::write_blob(data_directory, &content)?),
+            Err(err) => match err.kind() {
+                io::ErrorKind::NotFound => None,
+                _ => return Err(err),
+            },
+        };
+    }
+
+    Ok(())
+}
+
+pub fn check_file(file: &generations::models::File) -> io::Result<Option<models::Drift>> {
+    let metadata = match fs::symlink_metadata(&file.path) {
+        Ok(metadata) => Some(metadata),
+        Err(err) => match err.kind() {
+            io::ErrorKind::NotFound => None,
+            _ => return Err(err),
+        },
+    };
+
+    let hash = match (&file.hash, metadata) {
+        (None, None) => return Ok(None),
+        (None, Some(_)) => {
+            return Ok(Some(models::Drift::UnexpectedlyPresent {
+                path: file.path.clone(),
+            }))
+        }
+        (Some(_), None) => {
+            return Ok(Some(models::Drift::Missing {
+                path: file.path.clone(),
+            }))
+        }
+        (Some(hash), Some(metadata)) if metadata.is_file() => hash,
+        (Some(_), Some(_)) => {
+            return Ok(Some(models::Drift::Modified {
+                path: file.path.clone(),
+            }))
+        }
+    };
+
+    if store::hash(&fs::read(&file.path)?) != *hash {
+        return Ok(Some(models::Drift::Modified {
+            path: file.path.clone(),
+        }));
+    }
+
+    check_permissions(&file.path, &file.permissions)
+}
+
+fn check_directory(
+    directory: &generations::models::Directory,
+) -> io::Result<Option<models::Drift>> {
+    match (directory.present, directory.path.is_dir()) {
+        (true, true) => check_permissions(&directory.path, &directory.permissions),
+        (true, false) => Ok(Some(models::Drift::Missing {
+            path: directory.path.clone(),
+        })),
+        (false, true) => Ok(Some(models::Drift::UnexpectedlyPresent {
+            path: directory.path.clone(),
+        })),
+        (false, false) => Ok(None),
+    }
+}
+
+fn check_link(link: &generations::models::Link) -> io::Result<Option<models::Drift>> {
+    match fs::symlink_metadata(&link.path) {
+        Ok(metadata) if metadata.is_symlink() => {
+            let target = fs::read_link(&link.path)?;
+
+            if target == link.target {
+                Ok(None)
+            } else {
+                Ok(Some(models::Drift::Retargeted {
+                    path: link.path.clone(),
+                    expected: link.target.clone(),
+                    actual: target,
+                }))
+            }
+        }
+        Ok(_) => Ok(Some(models::Drift::Modified {
+            path: link.path.clone(),
+        })),
+        Err(err) => match err.kind() {
+            io::ErrorKind::NotFound => Ok(Some(models::Drift::Missing {
+                path: link.path.clone(),
+            })),
+            _ => Err(err),
+        },
+    }
+}
+
+/// Compares only the attributes the generation tracks.
+fn check_permissions(
+    path: &Path,
+    permissions: &generations::models::Permissions,
+) -> io::Result<Option<models::Drift>> {
+    let metadata = fs::metadata(path)?;
+    let mut expected = Vec::new();
+    let mut actual = Vec::new();
+
+    if let Some(mode) = permissions.mode {
+        if metadata.mode() & 0o7777 != mode {
+            expected.push(format!("mode={:04o}", mode));
+            actual.push(format!("mode={:04o}", metadata.mode() & 0o7777));
+        }
+    }
+
+    if let Some(owner) = &permissions.owner {
+        if metadata.uid() != apply::lookup_id(Path::new(apply::PASSWD_PATH), owner)? {
+            expected.push(format!("owner={}", owner));
+            actual.push(format!("owner={}", metadata.uid()));
+        }
+    }
+
+    if let Some(group) = &permissions.group {
+        if metadata.gid() != apply::lookup_id(Path::new(apply::GROUP_PATH), group)? {
+            expected.push(format!("group={}", group));
+            actual.push(format!("group={}", metadata.gid()));
+        }
+    }
+
+    if expected.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(models::Drift::Permissions {
+        path: path.to_path_buf(),
+        expected: expected.join(" "),
+        actual: actual.join(" "),
+    }))
+}
@@ -0,0 +1,83 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// What to do with files that were modified outside carbide before they are changed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolution {
+    /// Stop without changing anything.
+    Abort,
+    /// Overwrite the local changes.
+    Force,
+    /// Keep the local changes and record them in the generation.
+    Adopt,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Drift {
+    /// A managed file whose content differs from the generation.
+    Modified { path: PathBuf },
+    /// A managed file, directory or link that does not exist.
+    Missing { path: PathBuf },
+    /// A path the generation deletes that exists anyway.
+    UnexpectedlyPresent { path: PathBuf },
+    /// A managed path whose mode, owner or group differs from the generation.
+    Permissions {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+    /// A managed link that points somewhere else.
+    Retargeted {
+        path: PathBuf,
+        expected: PathBuf,
+        actual: PathBuf,
+    },
+}
+
+impl Drift {
+    pub fn path(&self) -> &Path {
+        match self {
+            Drift::Modified { path }
+            | Drift::Missing { path }
+            | Drift::UnexpectedlyPresent { path }
+            | Drift::Permissions { path, .. }
+            | Drift::Retargeted { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Drift::Modified { path } => write!(f, "( Modified ) {}", path.display()),
+            Drift::Missing { path } => write!(f, "( Missing ) {}", path.display()),
+            Drift::UnexpectedlyPresent { path } => {
+                write!(f, "( Unexpectedly Present ) {}", path.display())
+            }
+            Drift::Permissions {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "( Permissions ) {} expected {} found {}",
+                path.display(),
+                expected,
+                actual
+            ),
+            Drift::Retargeted {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "( Retargeted ) {} expected {} found {}",
+                path.display(),
+                expected.display(),
+                actual.display()
+            ),
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use std::{fs, os::unix::fs::PermissionsExt, path::PathBuf};
+
+use assert_fs::prelude::*;
+
+use crate::{
+    difference::{
+        self,
+        models::{Action, Difference, File as DifferenceFile},
+    },
+    drift::{self, models::Drift},
+    generations::models::{Directory, File, Generation, Link, Permissions},
+    store,
+};
+
+#[test]
+fn check_generation() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let unchanged_file = root.child("unchanged");
+    unchanged_file.write_str("Hello World").unwrap();
+    let modified_file = root.child("modified");
+    modified_file.write_str("Hello World 2").unwrap();
+    let present_file = root.child("present");
+    present_file.write_str("Hello World").unwrap();
+    let permissions_file = root.child("permissions");
+    permissions_file.write_str("Hello World").unwrap();
+    fs::set_permissions(permissions_file.path(), fs::Permissions::from_mode(0o644)).unwrap();
+    std::os::unix::fs::symlink("/home/carbide/other", root.child("link").path()).unwrap();
+
+    let generation = Generation {
+        id: 0,
+        files: vec![
+            File {
+                path: unchanged_file.to_path_buf(),
+                hash: Some(store::hash(b"Hello World")),
+                permissions: Permissions::default(),
+            },
+            File {
+                path: modified_file.to_path_buf(),
+                hash: Some(store::hash(b"Hello World")),
+                permissions: Permissions::default(),
+            },
+            File {
+                path: root.child("missing").to_path_buf(),
+                hash: Some(store::hash(b"Hello World")),
+                permissions: Permissions::default(),
+            },
+            File {
+                path: present_file.to_path_buf(),
+                hash: None,
+                permissions: Permissions::default(),
+            },
+            File {
+                path: permissions_file.to_path_buf(),
+                hash: Some(store::hash(b"Hello World")),
+                permissions: Permissions {
+                    mode: Some(0o600),
+                    owner: None,
+                    group: None,
+                },
+            },
+        ],
+        directories: vec![Directory {
+            path: root.child("directory").to_path_buf(),
+            present: true,
+            permissions: Permissions::default(),
+        }],
+        links: vec![Link {
+            target: PathBuf::from("/home/carbide/dotfiles"),
+            path: root.child("link").to_path_buf(),
+        }],
+        ..Generation::new()
+    };
+
+    assert_eq!(
+        drift::check_generation(&generation).unwrap(),
+        vec![
+            Drift::Modified {
+                path: modified_file.to_path_buf(),
+            },
+            Drift::Missing {
+                path: root.child("missing").to_path_buf(),
+            },
+            Drift::UnexpectedlyPresent {
+                path: present_file.to_path_buf(),
+            },
+            Drift::Permissions {
+                path: permissions_file.to_path_buf(),
+                expected: String::from("mode=0600"),
+                actual: String::from("mode=0644"),
+            },
+            Drift::Missing {
+                path: root.child("directory").to_path_buf(),
+            },
+            Drift::Retargeted {
+                path: root.child("link").to_path_buf(),
+                expected: PathBuf::from("/home/carbide/dotfiles"),
+                actual: PathBuf::from("/home/carbide/other"),
+            },
+        ]
+    );
+}
+
+#[test]
+fn check_difference() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let modified_file = root.child("modified");
+    modified_file.write_str("Local Content").unwrap();
+    let unchanged_file = root.child("unchanged");
+    unchanged_file.write_str("Hello World").unwrap();
+
+    let initial_generation = Generation {
+        id: 0,
+        files: vec![
+            File {
+                path: modified_file.to_path_buf(),
+                hash: Some(store::hash(b"Hello World")),
+                permissions: Permissions::default(),
+            },
+            File {
+                path: unchanged_file.to_path_buf(),
+                hash: Some(store::hash(b"Hello World")),
+                permissions: Permissions::default(),
+            },
+        ],
+        ..Generation::new()
+    };
+
+    let mut final_generation = Generation {
+        id: 1,
+        files: vec![
+            File {
+                path: modified_file.to_path_buf(),
+                hash: Some(store::hash(b"Final Content")),
+                permissions: Permissions::default(),
+            },
+            File {
+                path: unchanged_file.to_path_buf(),
+                hash: Some(store::hash(b"Final Content")),
+                permissions: Permissions::default(),
+            },
+        ],
+        ..Generation::new()
+    };
+
+    let mut difference = difference::differ_generations(&initial_generation, &final_generation);
+    let drifts = drift::check_difference(&initial_generation, &difference).unwrap();
+
+    assert_eq!(
+        drifts,
+        vec![Drift::Modified {
+            path: modified_file.to_path_buf(),
+        }]
+    );
+
+    drift::adopt_drifts(
+        root.child("data").path(),
+        &drifts,
+        &mut final_generation,
+        &mut difference,
+    )
+    .unwrap();
+
+    assert_eq!(
+        difference,
+        Difference {
+            actions: vec![Action::File(DifferenceFile::Update {
+                path: unchanged_file.to_path_buf(),
+                hash: store::hash(b"Final Content"),
+                permissions: Permissions::default(),
+            })]
+        }
+    );
+    assert_eq!(
+        final_generation.files[0].hash,
+        Some(store::hash(b"Local Content"))
+    );
+    assert_eq!(
+        store::read_blob(root.child("data").path(), &store::hash(b"Local Content")).unwrap(),
+        b"Local Content"
+    );
+}
+
+#[test]
+fn check_difference_missing_removed_file() {
+    let root = assert_fs::TempDir::new().unwrap();
+
+    let initial_generation = Generation {
+        id: 0,
+        files: vec![
+            File {
+                path: root.child("deleted").to_path_buf(),
+                hash: Some(store::hash(b"Hello World")),
+                permissions: Permissions::default(),
+            },
+            File {
+                path: root.child("unmanaged").to_path_buf(),
+                hash: Some(store::hash(b"Hello World")),
+                permissions: Permissions::default(),
+            },
+        ],
+        ..Generation::new()
+    };
+
+    let final_generation = Generation {
+        id: 1,
+        files: vec![File {
+            path: root.child("deleted").to_path_buf(),
+            hash: None,
+            permissions: Permissions::default(),
+        }],
+        ..Generation::new()
+    };
+
+    let difference = difference::differ_generations(&initial_generation, &final_generation);
+
+    // Files that are already gone are where the difference leaves them anyway.
+    assert_eq!(
+        drift::check_difference(&initial_generation, &difference).unwrap(),
+        vec![]
+    );
+}
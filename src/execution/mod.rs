@@ -0,0 +1,95 @@
+use std::{
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+};
+
+use crate::difference;
+
+pub mod models;
+#[cfg(test)]
+mod tests;
+
+pub fn run_command(
+    shell: &models::Shell,
+    working_directory: &Path,
+    command: &str,
+) -> io::Result<models::CommandOutput> {
+    let mut child = Command::new(&shell.program)
+        .args(&shell.arguments)
+        .arg(command)
+        .current_dir(working_directory)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stdout = child.stdout.take().expect("Child stdout is piped");
+    let stderr = child.stderr.take().expect("Child stderr is piped");
+
+    let stderr_thread = thread::spawn(move || stream_lines(stderr, "Command Error"));
+    let stdout = stream_lines(stdout, "Command Output")?;
+    let stderr = stderr_thread
+        .join()
+        .map_err(|_| io::Error::other("Command error reader panicked"))??;
+
+    let status = child.wait()?;
+
+    Ok(models::CommandOutput {
+        command: command.to_string(),
+        stdout,
+        stderr,
+        status,
+    })
+}
+
+pub fn run_script(
+    shell: &models::Shell,
+    working_directory: &Path,
+    script: &difference::models::Script,
+) -> io::Result<Vec<models::CommandOutput>> {
+    let mut outputs = Vec::new();
+
+    for command in script.commands() {
+        println!("[ Stage 4 ] ( Running Command ) {}", command);
+
+        let output = run_command(shell, working_directory, command).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!(
+                    "Error running command `{}` from generation {}: {}",
+                    command,
+                    script.generation_id(),
+                    err
+                ),
+            )
+        })?;
+
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "Command `{}` from generation {} failed with {}",
+                command,
+                script.generation_id(),
+                output.status
+            )));
+        }
+
+        outputs.push(output);
+    }
+
+    Ok(outputs)
+}
+
+fn stream_lines(reader: impl Read, label: &str) -> io::Result<String> {
+    let mut captured = String::new();
+
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        println!("[ Stage 4 ] ( {} ) {}", label, line);
+        captured.push_str(&line);
+        captured.push('\n');
+    }
+
+    Ok(captured)
+}
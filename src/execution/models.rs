@@ -0,0 +1,24 @@
+use std::{path::PathBuf, process::ExitStatus};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shell {
+    pub program: PathBuf,
+    pub arguments: Vec<String>,
+}
+
+impl Shell {
+    pub fn new(program: PathBuf) -> Self {
+        Self {
+            program,
+            arguments: vec![String::from("-c")],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandOutput {
+    pub command: String,
+    pub stdout: String,
+    pub stderr: String,
+    pub status: ExitStatus,
+}
@@ -0,0 +1,74 @@
+use std::path::PathBuf;
+
+use assert_fs::prelude::*;
+
+use crate::{
+    difference,
+    execution::{self, models::Shell},
+};
+
+#[test]
+fn run_command_captures_output() {
+    let working_directory = assert_fs::TempDir::new().unwrap();
+    let shell = Shell::new(PathBuf::from("/bin/sh"));
+
+    let output = execution::run_command(
+        &shell,
+        working_directory.path(),
+        "echo Hello World; echo Error >&2",
+    )
+    .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, "Hello World\n");
+    assert_eq!(output.stderr, "Error\n");
+}
+
+#[test]
+fn run_command_in_working_directory() {
+    let working_directory = assert_fs::TempDir::new().unwrap();
+    let shell = Shell::new(PathBuf::from("/bin/sh"));
+
+    execution::run_command(&shell, working_directory.path(), "echo Hello > output").unwrap();
+
+    working_directory.child("output").assert("Hello\n");
+}
+
+#[test]
+fn run_script() {
+    let working_directory = assert_fs::TempDir::new().unwrap();
+    let shell = Shell::new(PathBuf::from("/bin/sh"));
+
+    let outputs = execution::run_script(
+        &shell,
+        working_directory.path(),
+        &difference::models::Script::Install {
+            generation_id: 1,
+            commands: vec![String::from("true"), String::from("echo Hello")],
+        },
+    )
+    .unwrap();
+
+    assert_eq!(outputs.len(), 2);
+    assert_eq!(outputs[1].stdout, "Hello\n");
+}
+
+#[test]
+fn run_script_stops_on_failure() {
+    let working_directory = assert_fs::TempDir::new().unwrap();
+    let shell = Shell::new(PathBuf::from("/bin/sh"));
+
+    let err = execution::run_script(
+        &shell,
+        working_directory.path(),
+        &difference::models::Script::Install {
+            generation_id: 3,
+            commands: vec![String::from("false"), String::from("touch output")],
+        },
+    )
+    .unwrap_err();
+
+    assert!(err.to_string().contains("`false`"));
+    assert!(err.to_string().contains("generation 3"));
+    assert!(!working_directory.child("output").path().exists());
+}
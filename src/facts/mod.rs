@@ -0,0 +1,137 @@
+use std::{collections::BTreeMap, env, ffi::OsString, fs, io, thread};
+
+use crate::apply;
+
+pub mod models;
+#[cfg(test)]
+mod tests;
+
+const HOSTNAME_PATH: &str = "/proc/sys/kernel/hostname";
+const KERNEL_PATH: &str = "/proc/sys/kernel/osrelease";
+const OS_RELEASE_PATH: &str = "/etc/os-release";
+const MEMINFO_PATH: &str = "/proc/meminfo";
+
+/// Gathers the facts a config can branch on.
+pub trait Collector {
+    fn collect(&self) -> io::Result<models::Facts>;
+}
+
+/// Collects facts from the running system.
+pub struct SystemCollector;
+
+impl Collector for SystemCollector {
+    fn collect(&self) -> io::Result<models::Facts> {
+        Ok(models::Facts {
+            hostname: fs::read_to_string(HOSTNAME_PATH)?.trim().to_string(),
+            kernel: fs::read_to_string(KERNEL_PATH)?.trim().to_string(),
+            distro: match fs::read_to_string(OS_RELEASE_PATH) {
+                Ok(os_release) => parse_os_release(&os_release),
+                Err(err) => match err.kind() {
+                    io::ErrorKind::NotFound => models::Distro::default(),
+                    _ => return Err(err),
+                },
+            },
+            architecture: env::consts::ARCH.to_string(),
+            cpu_count: thread::available_parallelism().map_or(1, |count| count.get()),
+            memory: parse_meminfo(&fs::read_to_string(MEMINFO_PATH)?).unwrap_or_default(),
+            users: parse_passwd(&fs::read_to_string(apply::PASSWD_PATH)?),
+            groups: parse_group(&fs::read_to_string(apply::GROUP_PATH)?),
+            environment: parse_environment(env::vars_os()),
+        })
+    }
+}
+
+/// Fixed facts, so that configs can be evaluated as if on another machine.
+impl Collector for models::Facts {
+    fn collect(&self) -> io::Result<models::Facts> {
+        Ok(self.clone())
+    }
+}
+
+pub fn parse_os_release(os_release: &str) -> models::Distro {
+    let mut distro = models::Distro::default();
+
+    for line in os_release.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"').to_string();
+
+        match key.trim() {
+            "ID" => distro.id = value,
+            "NAME" => distro.name = value,
+            "VERSION_ID" => distro.version = value,
+            _ => {}
+        }
+    }
+
+    distro
+}
+
+/// Returns `MemTotal` in bytes.
+pub fn parse_meminfo(meminfo: &str) -> Option<u64> {
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|value| {
+            value
+                .trim()
+                .trim_end_matches("kB")
+                .trim()
+                .parse::<u64>()
+                .ok()
+        })
+        .map(|kilobytes| kilobytes * 1024)
+}
+
+pub fn parse_passwd(passwd: &str) -> Vec<models::User> {
+    passwd
+        .lines()
+        .filter_map(|line| {
+            let fields = line.split(':').collect::<Vec<_>>();
+            let [name, _, uid, gid, _, home, shell] = fields.as_slice() else {
+                return None;
+            };
+
+            Some(models::User {
+                name: name.to_string(),
+                uid: uid.parse().ok()?,
+                gid: gid.parse().ok()?,
+                home: home.to_string(),
+                shell: shell.to_string(),
+            })
+        })
+        .collect()
+}
+
+pub fn parse_group(group: &str) -> Vec<models::Group> {
+    group
+        .lines()
+        .filter_map(|line| {
+            let fields = line.split(':').collect::<Vec<_>>();
+            let [name, _, gid, members] = fields.as_slice() else {
+                return None;
+            };
+
+            Some(models::Group {
+                name: name.to_string(),
+                gid: gid.parse().ok()?,
+                members: members
+                    .split(',')
+                    .filter(|member| !member.is_empty())
+                    .map(String::from)
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+/// Keeps the environment variables whose name and value are valid UTF-8, the others cannot be
+/// handed to Lua as strings.
+pub fn parse_environment(
+    variables: impl Iterator<Item = (OsString, OsString)>,
+) -> BTreeMap<String, String> {
+    variables
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+        .collect()
+}
@@ -0,0 +1,40 @@
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Facts {
+    pub hostname: String,
+    /// Kernel release, e.g. `6.6.1-arch1-1`.
+    pub kernel: String,
+    pub distro: Distro,
+    pub architecture: String,
+    pub cpu_count: usize,
+    /// Total memory in bytes.
+    pub memory: u64,
+    pub users: Vec<User>,
+    pub groups: Vec<Group>,
+    pub environment: BTreeMap<String, String>,
+}
+
+/// Fields of `/etc/os-release`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Distro {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct User {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: String,
+    pub shell: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    pub name: String,
+    pub gid: u32,
+    pub members: Vec<String>,
+}
@@ -0,0 +1,83 @@
+use std::{collections::BTreeMap, ffi::OsString, os::unix::ffi::OsStringExt};
+
+use crate::facts::{self, models::Distro, models::Group, models::User};
+
+#[test]
+fn parse_os_release() {
+    assert_eq!(
+        facts::parse_os_release(
+            "NAME=\"Debian GNU/Linux\"\nVERSION_ID=\"12\"\nID=debian\n# Comment\n"
+        ),
+        Distro {
+            id: String::from("debian"),
+            name: String::from("Debian GNU/Linux"),
+            version: String::from("12"),
+        }
+    );
+}
+
+#[test]
+fn parse_meminfo() {
+    assert_eq!(
+        facts::parse_meminfo("MemTotal:       16318848 kB\nMemFree:         1024 kB\n"),
+        Some(16318848 * 1024)
+    );
+    assert_eq!(facts::parse_meminfo("MemFree: 1024 kB\n"), None);
+}
+
+#[test]
+fn parse_passwd_and_group() {
+    assert_eq!(
+        facts::parse_passwd(
+            "root:x:0:0:root:/root:/bin/sh\nnandesh:x:1000:1000::/home/nandesh:/bin/zsh\n"
+        ),
+        vec![
+            User {
+                name: String::from("root"),
+                uid: 0,
+                gid: 0,
+                home: String::from("/root"),
+                shell: String::from("/bin/sh"),
+            },
+            User {
+                name: String::from("nandesh"),
+                uid: 1000,
+                gid: 1000,
+                home: String::from("/home/nandesh"),
+                shell: String::from("/bin/zsh"),
+            },
+        ]
+    );
+    assert_eq!(
+        facts::parse_group("root:x:0:\nwheel:x:10:root,nandesh\n"),
+        vec![
+            Group {
+                name: String::from("root"),
+                gid: 0,
+                members: vec![],
+            },
+            Group {
+                name: String::from("wheel"),
+                gid: 10,
+                members: vec![String::from("root"), String::from("nandesh")],
+            },
+        ]
+    );
+}
+
+#[test]
+fn parse_environment() {
+    let variables = vec![
+        (OsString::from("EDITOR"), OsString::from("nvim")),
+        (
+            OsString::from("INVALID"),
+            OsString::from_vec(vec![0x66, 0x80]),
+        ),
+        (OsString::from_vec(vec![0xff]), OsString::from("value")),
+    ];
+
+    assert_eq!(
+        facts::parse_environment(variables.into_iter()),
+        BTreeMap::from([(String::from("EDITOR"), String::from("nvim"))])
+    );
+}
@@ -0,0 +1,152 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::{fchown, MetadataExt, OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+#[cfg(test)]
+mod tests;
+
+/// Mode and ownership a written file ends up with. Attributes left unset are kept from the
+/// replaced file, or take the defaults for new files.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Attributes {
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+/// Replaces the content of a file so that a crash leaves either the old or the new content
+/// behind. An existing target keeps its mode and ownership.
+pub fn write_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    write_atomically_with_attributes(path, content, &Attributes::default())
+}
+
+/// Replaces the content of a file so that a crash leaves either the old or the new content
+/// behind. The content is written to a new temporary file in the same directory, synced and
+/// then renamed over the target. The temporary file only becomes accessible to others once it
+/// has its final mode and ownership, before any content is written to it. When the target is
+/// a symlink, the file it points to is replaced and the link is kept.
+pub fn write_atomically_with_attributes(
+    path: &Path,
+    content: &[u8],
+    attributes: &Attributes,
+) -> io::Result<()> {
+    let path = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_symlink() => fs::canonicalize(path).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("Error resolving symlink {}: {}", path.display(), err),
+            )
+        })?,
+        _ => path.to_path_buf(),
+    };
+
+    let existing_metadata = match fs::symlink_metadata(&path) {
+        Ok(metadata) => Some(metadata),
+        Err(err) => match err.kind() {
+            io::ErrorKind::NotFound => None,
+            _ => return Err(err),
+        },
+    };
+
+    let attributes = Attributes {
+        mode: attributes
+            .mode
+            .or(existing_metadata.as_ref().map(|metadata| metadata.mode()))
+            .map(|mode| mode & 0o7777),
+        uid: attributes
+            .uid
+            .or(existing_metadata.as_ref().map(|metadata| metadata.uid())),
+        gid: attributes
+            .gid
+            .or(existing_metadata.as_ref().map(|metadata| metadata.gid())),
+    };
+
+    let (mut file, temporary_path) = create_temporary_file(&path)?;
+
+    let result = write_temporary_file(&mut file, content, &attributes)
+        .and_then(|_| fs::rename(&temporary_path, &path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary_path);
+    }
+    result?;
+
+    sync_parent_directory(&path)
+}
+
+/// Creates a hidden temporary file next to `path` that is only accessible to its owner. The
+/// file is always newly created, so a file or symlink planted at its path is never followed.
+fn create_temporary_file(path: &Path) -> io::Result<(File, PathBuf)> {
+    let file_name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid file path: {}", path.display()),
+        )
+    })?;
+
+    for attempt in 0.. {
+        let temporary_path = path.with_file_name(format!(
+            ".{}.carbide-tmp-{}-{}",
+            file_name.to_string_lossy(),
+            std::process::id(),
+            attempt
+        ));
+
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&temporary_path)
+        {
+            Ok(file) => return Ok((file, temporary_path)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    unreachable!()
+}
+
+fn write_temporary_file(
+    file: &mut File,
+    content: &[u8],
+    attributes: &Attributes,
+) -> io::Result<()> {
+    if attributes.uid.is_some() || attributes.gid.is_some() {
+        fchown(&*file, attributes.uid, attributes.gid)?;
+    }
+
+    // Changing the owner clears setuid and setgid bits, so the mode is set afterwards.
+    let mode = match attributes.mode {
+        Some(mode) => mode,
+        None => 0o666 & !read_umask(),
+    };
+    file.set_permissions(fs::Permissions::from_mode(mode))?;
+
+    file.write_all(content)?;
+    file.sync_all()
+}
+
+/// Returns the umask new files are created with, falling back to the common `022` when the
+/// kernel does not report it.
+fn read_umask() -> u32 {
+    fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix("Umask:"))
+                .and_then(|umask| u32::from_str_radix(umask.trim(), 8).ok())
+        })
+        .unwrap_or(0o022)
+}
+
+fn sync_parent_directory(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => File::open(parent)?.sync_all(),
+        _ => Ok(()),
+    }
+}
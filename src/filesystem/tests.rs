@@ -0,0 +1,79 @@
+use std::{
+    fs,
+    os::unix::fs::{symlink, PermissionsExt},
+};
+
+use assert_fs::prelude::*;
+
+use crate::filesystem;
+
+#[test]
+fn write_atomically_creates_file() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let file = root.child("file");
+
+    filesystem::write_atomically(file.path(), b"Hello World").unwrap();
+
+    file.assert("Hello World");
+    assert_eq!(fs::read_dir(root.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn write_atomically_keeps_mode() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let file = root.child("file");
+    file.write_str("Initial Content").unwrap();
+    fs::set_permissions(file.path(), fs::Permissions::from_mode(0o640)).unwrap();
+
+    filesystem::write_atomically(file.path(), b"Final Content").unwrap();
+
+    file.assert("Final Content");
+    assert_eq!(
+        fs::metadata(file.path()).unwrap().permissions().mode() & 0o7777,
+        0o640
+    );
+}
+
+#[test]
+fn write_atomically_missing_directory() {
+    let root = assert_fs::TempDir::new().unwrap();
+
+    assert!(filesystem::write_atomically(root.child("missing/file").path(), b"").is_err());
+    assert!(!root.child("missing").path().exists());
+}
+
+#[test]
+fn write_atomically_with_attributes() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let file = root.child("secret");
+
+    filesystem::write_atomically_with_attributes(
+        file.path(),
+        b"Hello World",
+        &filesystem::Attributes {
+            mode: Some(0o600),
+            ..filesystem::Attributes::default()
+        },
+    )
+    .unwrap();
+
+    file.assert("Hello World");
+    assert_eq!(
+        fs::metadata(file.path()).unwrap().permissions().mode() & 0o7777,
+        0o600
+    );
+}
+
+#[test]
+fn write_atomically_through_symlink() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let target = root.child("target");
+    target.write_str("Initial Content").unwrap();
+    let link = root.child("link");
+    symlink(target.path(), link.path()).unwrap();
+
+    filesystem::write_atomically(link.path(), b"Final Content").unwrap();
+
+    assert!(fs::symlink_metadata(link.path()).unwrap().is_symlink());
+    target.assert("Final Content");
+}
@@ -0,0 +1,478 @@
+//! On-disk layout of generation files.
+//!
+//! A generation file starts with a header made of the magic bytes, the format version and a
+//! CRC-32 checksum of the payload, all little endian, followed by the bincode payload. Files
+//! written before the header was introduced hold a bare bincode payload and are read as
+//! version 0. Older versions are migrated to the current model when read. File contents that
+//! older versions kept inline are moved into blobs held in memory, so that reading never
+//! writes to the store.
+
+use std::{io, path::PathBuf};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use super::models;
+use crate::store;
+
+pub const MAGIC: &[u8; 4] = b"CRBD";
+pub const CURRENT_VERSION: u32 = 6;
+const HEADER_LENGTH: usize = 12;
+
+pub fn encode(generation: &models::Generation) -> io::Result<Vec<u8>> {
+    let payload = bincode::serialize(generation)
+        .map_err(|err| io::Error::other(format!("Bincode error: {}", err)))?;
+
+    let mut content = Vec::with_capacity(HEADER_LENGTH + payload.len());
+    content.extend_from_slice(MAGIC);
+    content.extend_from_slice(&CURRENT_VERSION.to_le_bytes());
+    content.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    content.extend_from_slice(&payload);
+
+    Ok(content)
+}
+
+pub fn decode(content: &[u8], blobs: &mut store::models::Blobs) -> io::Result<models::Generation> {
+    if !content.starts_with(MAGIC) {
+        return deserialize::<v0::Generation>(content)
+            .map(v1::Generation::from)
+            .map(|generation| generation.migrate(blobs))
+            .map(v3::Generation::from)
+            .map(v4::Generation::from)
+            .map(v5::Generation::from)
+            .map(models::Generation::from);
+    }
+
+    if content.len() < HEADER_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Truncated generation header",
+        ));
+    }
+
+    let version = u32::from_le_bytes(content[4..8].try_into().unwrap());
+    let checksum = u32::from_le_bytes(content[8..12].try_into().unwrap());
+    let payload = &content[HEADER_LENGTH..];
+
+    if version > CURRENT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unsupported generation format version: {}", version),
+        ));
+    }
+
+    if crc32fast::hash(payload) != checksum {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Generation checksum mismatch",
+        ));
+    }
+
+    match version {
+        1 => deserialize::<v1::Generation>(payload)
+            .map(|generation| generation.migrate(blobs))
+            .map(v3::Generation::from)
+            .map(v4::Generation::from)
+            .map(v5::Generation::from)
+            .map(models::Generation::from),
+        2 => deserialize::<v2::Generation>(payload)
+            .map(v3::Generation::from)
+            .map(v4::Generation::from)
+            .map(v5::Generation::from)
+            .map(models::Generation::from),
+        3 => deserialize::<v3::Generation>(payload)
+            .map(v4::Generation::from)
+            .map(v5::Generation::from)
+            .map(models::Generation::from),
+        4 => deserialize::<v4::Generation>(payload)
+            .map(v5::Generation::from)
+            .map(models::Generation::from),
+        5 => deserialize::<v5::Generation>(payload).map(models::Generation::from),
+        6 => deserialize::<models::Generation>(payload),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unsupported generation format version: {}", version),
+        )),
+    }
+}
+
+fn deserialize<'a, T: Deserialize<'a>>(payload: &'a [u8]) -> io::Result<T> {
+    bincode::deserialize(payload).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Bincode error: {}", err),
+        )
+    })
+}
+
+/// Generations written before the versioned header, with only files and scripts.
+pub mod v0 {
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Generation {
+        pub id: i32,
+        pub creation_datetime: DateTime<Local>,
+        pub files: Vec<File>,
+        pub scripts: Vec<Script>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct File {
+        pub path: PathBuf,
+        pub content: Option<String>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Script {
+        pub install: Vec<String>,
+        pub update: Vec<String>,
+        pub uninstall: Vec<String>,
+    }
+}
+
+/// Generations with permissions, directories and links, keeping file contents inline.
+pub mod v1 {
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Generation {
+        pub id: i32,
+        pub creation_datetime: DateTime<Local>,
+        pub files: Vec<File>,
+        pub directories: Vec<Directory>,
+        pub links: Vec<Link>,
+        pub scripts: Vec<Script>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct File {
+        pub path: PathBuf,
+        pub content: Option<String>,
+        pub permissions: Permissions,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Directory {
+        pub path: PathBuf,
+        pub present: bool,
+        pub permissions: Permissions,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Link {
+        pub target: PathBuf,
+        pub path: PathBuf,
+    }
+
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    pub struct Permissions {
+        pub mode: Option<u32>,
+        pub owner: Option<String>,
+        pub group: Option<String>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Script {
+        pub name: Option<String>,
+        pub install: Vec<String>,
+        pub update: Vec<String>,
+        pub uninstall: Vec<String>,
+    }
+
+    impl Generation {
+        pub fn migrate(self, blobs: &mut store::models::Blobs) -> v2::Generation {
+            let files = self
+                .files
+                .into_iter()
+                .map(|file| v2::File {
+                    hash: file.content.map(|content| blobs.add(content.into_bytes())),
+                    path: file.path,
+                    permissions: file.permissions,
+                })
+                .collect();
+
+            v2::Generation {
+                id: self.id,
+                creation_datetime: self.creation_datetime,
+                files,
+                directories: self.directories,
+                links: self.links,
+                scripts: self.scripts,
+            }
+        }
+    }
+}
+
+/// Generations referencing file contents in the store, without a profile.
+pub mod v2 {
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Generation {
+        pub id: i32,
+        pub creation_datetime: DateTime<Local>,
+        pub files: Vec<File>,
+        pub directories: Vec<v1::Directory>,
+        pub links: Vec<v1::Link>,
+        pub scripts: Vec<v1::Script>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct File {
+        pub path: PathBuf,
+        pub hash: Option<String>,
+        pub permissions: v1::Permissions,
+    }
+}
+
+/// Generations with a profile, without packages.
+pub mod v3 {
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Generation {
+        pub id: i32,
+        pub creation_datetime: DateTime<Local>,
+        pub profile: Option<String>,
+        pub files: Vec<v2::File>,
+        pub directories: Vec<v1::Directory>,
+        pub links: Vec<v1::Link>,
+        pub scripts: Vec<v1::Script>,
+    }
+}
+
+/// Generations with packages, without services.
+pub mod v4 {
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Generation {
+        pub id: i32,
+        pub creation_datetime: DateTime<Local>,
+        pub profile: Option<String>,
+        pub files: Vec<v2::File>,
+        pub directories: Vec<v1::Directory>,
+        pub links: Vec<v1::Link>,
+        pub packages: Vec<Package>,
+        pub scripts: Vec<v1::Script>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Package {
+        pub name: String,
+        pub manager: String,
+    }
+}
+
+/// Generations with services, without hooks.
+pub mod v5 {
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Generation {
+        pub id: i32,
+        pub creation_datetime: DateTime<Local>,
+        pub profile: Option<String>,
+        pub files: Vec<v2::File>,
+        pub directories: Vec<v1::Directory>,
+        pub links: Vec<v1::Link>,
+        pub packages: Vec<v4::Package>,
+        pub services: Vec<Service>,
+        pub scripts: Vec<v1::Script>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Service {
+        pub name: String,
+        pub enabled: Option<bool>,
+        pub running: Option<bool>,
+        pub restart_on: Vec<PathBuf>,
+    }
+}
+
+impl From<v0::Generation> for v1::Generation {
+    fn from(generation: v0::Generation) -> Self {
+        Self {
+            id: generation.id,
+            creation_datetime: generation.creation_datetime,
+            files: generation
+                .files
+                .into_iter()
+                .map(|file| v1::File {
+                    path: file.path,
+                    content: file.content,
+                    permissions: v1::Permissions::default(),
+                })
+                .collect(),
+            directories: Vec::new(),
+            links: Vec::new(),
+            scripts: generation
+                .scripts
+                .into_iter()
+                .map(|script| v1::Script {
+                    name: None,
+                    install: script.install,
+                    update: script.update,
+                    uninstall: script.uninstall,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<v2::Generation> for v3::Generation {
+    fn from(generation: v2::Generation) -> Self {
+        Self {
+            id: generation.id,
+            creation_datetime: generation.creation_datetime,
+            profile: None,
+            files: generation.files,
+            directories: generation.directories,
+            links: generation.links,
+            scripts: generation.scripts,
+        }
+    }
+}
+
+impl From<v3::Generation> for v4::Generation {
+    fn from(generation: v3::Generation) -> Self {
+        Self {
+            id: generation.id,
+            creation_datetime: generation.creation_datetime,
+            profile: generation.profile,
+            files: generation.files,
+            directories: generation.directories,
+            links: generation.links,
+            packages: Vec::new(),
+            scripts: generation.scripts,
+        }
+    }
+}
+
+impl From<v4::Generation> for v5::Generation {
+    fn from(generation: v4::Generation) -> Self {
+        Self {
+            id: generation.id,
+            creation_datetime: generation.creation_datetime,
+            profile: generation.profile,
+            files: generation.files,
+            directories: generation.directories,
+            links: generation.links,
+            packages: generation.packages,
+            services: Vec::new(),
+            scripts: generation.scripts,
+        }
+    }
+}
+
+impl From<v5::Generation> for models::Generation {
+    fn from(generation: v5::Generation) -> Self {
+        Self {
+            id: generation.id,
+            creation_datetime: generation.creation_datetime,
+            profile: generation.profile,
+            files: generation
+                .files
+                .into_iter()
+                .map(models::File::from)
+                .collect(),
+            directories: generation
+                .directories
+                .into_iter()
+                .map(models::Directory::from)
+                .collect(),
+            links: generation
+                .links
+                .into_iter()
+                .map(models::Link::from)
+                .collect(),
+            packages: generation
+                .packages
+                .into_iter()
+                .map(models::Package::from)
+                .collect(),
+            services: generation
+                .services
+                .into_iter()
+                .map(models::Service::from)
+                .collect(),
+            hooks: Vec::new(),
+            scripts: generation
+                .scripts
+                .into_iter()
+                .map(models::Script::from)
+                .collect(),
+        }
+    }
+}
+
+impl From<v1::Permissions> for models::Permissions {
+    fn from(permissions: v1::Permissions) -> Self {
+        Self {
+            mode: permissions.mode,
+            owner: permissions.owner,
+            group: permissions.group,
+        }
+    }
+}
+
+impl From<v1::Directory> for models::Directory {
+    fn from(directory: v1::Directory) -> Self {
+        Self {
+            path: directory.path,
+            present: directory.present,
+            permissions: directory.permissions.into(),
+        }
+    }
+}
+
+impl From<v1::Link> for models::Link {
+    fn from(link: v1::Link) -> Self {
+        Self {
+            target: link.target,
+            path: link.path,
+        }
+    }
+}
+
+impl From<v1::Script> for models::Script {
+    fn from(script: v1::Script) -> Self {
+        Self {
+            name: script.name,
+            install: script.install,
+            update: script.update,
+            uninstall: script.uninstall,
+        }
+    }
+}
+
+impl From<v2::File> for models::File {
+    fn from(file: v2::File) -> Self {
+        Self {
+            path: file.path,
+            hash: file.hash,
+            permissions: file.permissions.into(),
+        }
+    }
+}
+
+impl From<v4::Package> for models::Package {
+    fn from(package: v4::Package) -> Self {
+        Self {
+            name: package.name,
+            manager: package.manager,
+        }
+    }
+}
+
+impl From<v5::Service> for models::Service {
+    fn from(service: v5::Service) -> Self {
+        Self {
+            name: service.name,
+            enabled: service.enabled,
+            running: service.running,
+            restart_on: service.restart_on,
+        }
+    }
+}
@@ -1,39 +1,162 @@
 use regex::Regex;
-use std::{fs, io, path::PathBuf};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
 
+use crate::{filesystem, store};
+
+mod format;
 pub mod models;
 #[cfg(test)]
 mod tests;
 
-pub fn read_generations(path: &PathBuf) -> io::Result<Vec<models::Generation>> {
-    let mut generations: Vec<models::Generation> = Vec::new();
+const CURRENT_GENERATION_FILE_NAME: &str = "current";
+const CREATED_DIRECTORIES_FILE_NAME: &str = "directories";
 
-    for entry in fs::read_dir(path)? {
-        let entry = entry?;
-        let path = entry.path();
+pub fn generation_path(directory: &Path, id: i32) -> PathBuf {
+    directory.join(format!("carbide-{}", id))
+}
 
-        if !path.is_file() {
-            continue;
+pub fn read_generation(directory: &Path, id: i32) -> io::Result<models::Generation> {
+    read_generation_with_blobs(directory, id, &mut store::models::Blobs::default())
+}
+
+/// Reads a generation like `read_generation`, keeping in `blobs` the contents that an older
+/// format held inline and that may not be in the store.
+pub fn read_generation_with_blobs(
+    directory: &Path,
+    id: i32,
+    blobs: &mut store::models::Blobs,
+) -> io::Result<models::Generation> {
+    models::Generation::from_file(&generation_path(directory, id), blobs)
+}
+
+/// Returns the ID of the generation that was last applied successfully. Data directories
+/// from before the marker was introduced have none, their newest generation is the applied
+/// one. Generations written since then start with a header, so one of those without a marker
+/// was never applied.
+pub fn read_current_generation_id(directory: &Path) -> io::Result<i32> {
+    let id = match fs::read_to_string(directory.join(CURRENT_GENERATION_FILE_NAME)) {
+        Ok(id) => id,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return match read_generation_ids(directory) {
+                Ok(ids) => match ids.last() {
+                    Some(&id)
+                        if !fs::read(generation_path(directory, id))?
+                            .starts_with(format::MAGIC) =>
+                    {
+                        Ok(id)
+                    }
+                    _ => Err(err),
+                },
+                Err(ids_err) if ids_err.kind() == io::ErrorKind::NotFound => Err(err),
+                Err(ids_err) => Err(ids_err),
+            };
         }
+        Err(err) => return Err(err),
+    };
+
+    id.trim().parse::<i32>().map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Error parsing current generation id: {}", err),
+        )
+    })
+}
 
-        let file_name_regex = Regex::new(r"^carbide-\d+$").unwrap();
+pub fn read_current_generation(directory: &Path) -> io::Result<models::Generation> {
+    read_generation(directory, read_current_generation_id(directory)?)
+}
 
-        if let Some(file_name) = path.file_name().and_then(|n| n.to_str()) {
-            if !file_name_regex.is_match(file_name) {
-                continue;
-            }
+pub fn read_current_generation_with_blobs(
+    directory: &Path,
+    blobs: &mut store::models::Blobs,
+) -> io::Result<models::Generation> {
+    read_generation_with_blobs(directory, read_current_generation_id(directory)?, blobs)
+}
 
-            generations.push(models::Generation::from_file(&path)?)
-        }
+/// Marks a generation as applied. Only call this after the generation has been applied
+/// successfully.
+pub fn write_current_generation_id(directory: &Path, id: i32) -> io::Result<()> {
+    fs::create_dir_all(directory)?;
+    filesystem::write_atomically(
+        &directory.join(CURRENT_GENERATION_FILE_NAME),
+        id.to_string().as_bytes(),
+    )
+}
+
+/// Returns the directories that carbide created while applying generations.
+pub fn read_created_directories(directory: &Path) -> io::Result<Vec<PathBuf>> {
+    match fs::read_to_string(directory.join(CREATED_DIRECTORIES_FILE_NAME)) {
+        Ok(created_directories) => Ok(created_directories.lines().map(PathBuf::from).collect()),
+        Err(err) => match err.kind() {
+            io::ErrorKind::NotFound => Ok(Vec::new()),
+            _ => Err(err),
+        },
     }
+}
 
-    generations.sort_by_key(|g| g.id);
+pub fn write_created_directories(
+    directory: &Path,
+    created_directories: &[PathBuf],
+) -> io::Result<()> {
+    let mut content = String::new();
+    for created_directory in created_directories {
+        content.push_str(&created_directory.to_string_lossy());
+        content.push('\n');
+    }
 
-    Ok(generations)
+    fs::create_dir_all(directory)?;
+    filesystem::write_atomically(
+        &directory.join(CREATED_DIRECTORIES_FILE_NAME),
+        content.as_bytes(),
+    )
+}
+
+pub fn delete_generation(directory: &Path, id: i32) -> io::Result<()> {
+    fs::remove_file(generation_path(directory, id))
+}
+
+/// Returns the generations that `clean` should remove, oldest first. The active generation
+/// is never returned. When no generation is known to be active, the newest one is kept in
+/// its place.
+pub fn expired_generations<'a>(
+    generations: &'a [models::Generation],
+    active_id: i32,
+    policy: &models::RetentionPolicy,
+) -> Vec<&'a models::Generation> {
+    let mut sorted_generations: Vec<&models::Generation> = generations.iter().collect();
+    sorted_generations.sort_by_key(|generation| std::cmp::Reverse(generation.id));
+
+    let active_id = match sorted_generations.first() {
+        Some(newest_generation)
+            if !generations
+                .iter()
+                .any(|generation| generation.id == active_id) =>
+        {
+            newest_generation.id
+        }
+        _ => active_id,
+    };
+
+    let mut expired_generations: Vec<&models::Generation> = sorted_generations
+        .into_iter()
+        .enumerate()
+        .filter(|(newer_generation_count, generation)| {
+            generation.id != active_id && !policy.is_retained(generation, *newer_generation_count)
+        })
+        .map(|(_, generation)| generation)
+        .collect();
+
+    expired_generations.reverse();
+    expired_generations
 }
 
-pub fn read_last_generation(path: &PathBuf) -> io::Result<models::Generation> {
-    let mut highest_id = -1;
+/// Returns the IDs of all generation files on disk in ascending order, without reading them.
+pub fn read_generation_ids(path: &Path) -> io::Result<Vec<i32>> {
+    let mut ids = Vec::new();
+    let file_name_regex = Regex::new(r"^carbide-(\d+)$").unwrap();
 
     for entry in fs::read_dir(path)? {
         let entry = entry?;
@@ -43,34 +166,41 @@ pub fn read_last_generation(path: &PathBuf) -> io::Result<models::Generation> {
             continue;
         }
 
-        let file_name_regex = Regex::new(r"^carbide-(\d+)$").unwrap();
-
         if let Some(file_name) = path.file_name().and_then(|n| n.to_str()) {
             if let Some(captures) = file_name_regex.captures(file_name) {
                 let id = captures.get(1).unwrap();
 
-                let id = i32::from_str_radix(id.as_str(), 10).map_err(|err| {
+                ids.push(id.as_str().parse::<i32>().map_err(|err| {
                     io::Error::new(
                         io::ErrorKind::InvalidData,
                         format!("Error parsing generation id: {}", err),
                     )
-                })?;
-
-                if id > highest_id {
-                    highest_id = id
-                }
+                })?);
             }
         }
     }
 
-    if highest_id == -1 {
-        return Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            "No generation files found",
-        ));
-    }
+    ids.sort();
+
+    Ok(ids)
+}
+
+/// Reads every generation on disk, skipping generation files that are corrupt or in an
+/// unknown format so that one bad file does not hide the rest.
+pub fn read_generations(path: &Path) -> io::Result<Vec<models::Generation>> {
+    Ok(read_generation_ids(path)?
+        .into_iter()
+        .filter_map(|id| read_generation(path, id).ok())
+        .collect())
+}
 
-    Ok(models::Generation::from_file(
-        &path.join(PathBuf::from(format!("carbide-{}", highest_id))),
-    )?)
+/// Returns the ID to use for a new generation, one above the highest generation on disk.
+pub fn next_generation_id(path: &Path) -> io::Result<i32> {
+    match read_generation_ids(path) {
+        Ok(ids) => Ok(ids.last().map_or(0, |id| id + 1)),
+        Err(err) => match err.kind() {
+            io::ErrorKind::NotFound => Ok(0),
+            _ => Err(err),
+        },
+    }
 }
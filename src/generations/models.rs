@@ -1,13 +1,24 @@
-use crate::lua;
-use chrono::{DateTime, Local};
+use super::format;
+use crate::{filesystem, lua, services, store};
+use chrono::{DateTime, Local, NaiveDate, TimeDelta};
 use serde::{Deserialize, Serialize};
-use std::{fs, io, path::PathBuf};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct Generation {
     pub id: i32,
     pub creation_datetime: DateTime<Local>,
+    /// The profile the generation was built for, if one was selected.
+    pub profile: Option<String>,
     pub files: Vec<File>,
+    pub directories: Vec<Directory>,
+    pub links: Vec<Link>,
+    pub packages: Vec<Package>,
+    pub services: Vec<Service>,
+    pub hooks: Vec<Hook>,
     pub scripts: Vec<Script>,
 }
 
@@ -16,36 +27,163 @@ impl Generation {
         Self {
             id: -1,
             creation_datetime: Local::now(),
+            profile: None,
             files: Vec::new(),
+            directories: Vec::new(),
+            links: Vec::new(),
+            packages: Vec::new(),
+            services: Vec::new(),
+            hooks: Vec::new(),
             scripts: Vec::new(),
         }
     }
 
-    pub fn from_file(path: &PathBuf) -> io::Result<Self> {
-        let file = fs::File::open(path)?;
-        bincode::deserialize_from(file)
-            .map_err(|err| io::Error::new(io::ErrorKind::Other, format!("Bincode Error: {}", err)))
+    /// Contents that an older format kept inline are added to `blobs`, not to the store.
+    pub fn from_file(path: &PathBuf, blobs: &mut store::models::Blobs) -> io::Result<Self> {
+        let content = fs::read(path)?;
+
+        format::decode(&content, blobs)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))
     }
 
+    /// File contents are added to `blobs`, they only reach the store once the generation is
+    /// applied.
     pub fn from_lua_config(
         config: &lua::models::Config,
         id: i32,
         creation_datetime: &DateTime<Local>,
+        blobs: &mut store::models::Blobs,
     ) -> Result<Self, String> {
         let mut files = Vec::<File>::new();
+        let mut contents = Vec::<Option<Vec<u8>>>::new();
+        let mut directories = Vec::<Directory>::new();
+        let mut links = Vec::<Link>::new();
+        let mut packages = Vec::<Package>::new();
+        let mut services = Vec::<Service>::new();
+        let mut hooks = Vec::<Hook>::new();
         let mut scripts = Vec::<Script>::new();
 
         for action in &config.actions {
             match action {
                 lua::models::Action::Script(script) => {
+                    if let Some(name) = &script.name {
+                        for existing_script in scripts.iter() {
+                            if existing_script.name.as_ref() == Some(name) {
+                                return Err(format!("Duplicate script with name: {}", name));
+                            }
+                        }
+                    }
+
                     scripts.push(Script {
+                        name: script.name.clone(),
                         install: script.install.clone(),
                         uninstall: script.uninstall.clone(),
                         update: script.update.clone(),
                     });
                 }
+                lua::models::Action::Package(package) => {
+                    let package = Package {
+                        name: package.name.clone(),
+                        manager: package.manager.clone(),
+                    };
+
+                    if packages.contains(&package) {
+                        return Err(format!(
+                            "Duplicate package: {} ({})",
+                            package.name, package.manager
+                        ));
+                    }
+
+                    packages.push(package);
+                }
+                lua::models::Action::Service(service) => {
+                    if services
+                        .iter()
+                        .any(|existing_service| existing_service.name == service.name)
+                    {
+                        return Err(format!("Duplicate service: {}", service.name));
+                    }
+
+                    // The unit file is managed like any other file, so that its changes show
+                    // up in the difference.
+                    if let Some(unit) = &service.unit {
+                        let path = services::unit_path(&service.name);
+
+                        if files.iter().any(|existing_file| existing_file.path == path) {
+                            return Err(format!("Duplicate file with path: {}", path.display()));
+                        }
+
+                        files.push(File {
+                            path,
+                            hash: None,
+                            permissions: Permissions::default(),
+                        });
+                        contents.push(Some(unit.clone()));
+                    }
+
+                    services.push(Service {
+                        name: service.name.clone(),
+                        enabled: service.enabled,
+                        running: service.running,
+                        restart_on: service.restart_on.clone(),
+                    });
+                }
+                lua::models::Action::Hook(hook) => hooks.push(Hook {
+                    paths: hook.paths.clone(),
+                    commands: hook.commands.clone(),
+                }),
+                lua::models::Action::Link(link) => {
+                    for existing_link in links.iter() {
+                        if link.path == existing_link.path {
+                            return Err(format!(
+                                "Duplicate link with path: {}",
+                                link.path.display()
+                            ));
+                        }
+                    }
+
+                    links.push(Link {
+                        target: link.target.clone(),
+                        path: link.path.clone(),
+                    });
+                }
+                lua::models::Action::Directory(directory) => {
+                    let (path, present, permissions) = match directory {
+                        lua::models::Directory::Create { path, permissions } => (
+                            path,
+                            true,
+                            Permissions {
+                                mode: permissions.mode,
+                                owner: permissions.owner.clone(),
+                                group: permissions.group.clone(),
+                            },
+                        ),
+                        lua::models::Directory::Delete { path } => {
+                            (path, false, Permissions::default())
+                        }
+                    };
+
+                    for existing_directory in directories.iter() {
+                        if *path == existing_directory.path {
+                            return Err(format!(
+                                "Duplicate directory with path: {}",
+                                path.display()
+                            ));
+                        }
+                    }
+
+                    directories.push(Directory {
+                        path: path.to_path_buf(),
+                        present,
+                        permissions,
+                    });
+                }
                 lua::models::Action::File(file) => match file {
-                    lua::models::File::Set { path, content } => {
+                    lua::models::File::Set {
+                        path,
+                        content,
+                        permissions,
+                    } => {
                         for existing_file in files.iter() {
                             if *path == existing_file.path {
                                 return Err(format!(
@@ -57,17 +195,27 @@ impl Generation {
 
                         files.push(File {
                             path: path.to_path_buf(),
-                            content: Some(content.to_string()),
+                            hash: None,
+                            permissions: Permissions {
+                                mode: permissions.mode,
+                                owner: permissions.owner.clone(),
+                                group: permissions.group.clone(),
+                            },
                         });
+                        contents.push(Some(content.clone()));
                     }
                     lua::models::File::Append { path, content } => {
                         let mut file_exists = false;
 
-                        for existing_file in files.iter_mut() {
+                        for (existing_file, existing_content) in
+                            files.iter().zip(contents.iter_mut())
+                        {
                             if *path == existing_file.path {
-                                existing_file.content = match &existing_file.content {
-                                    Some(original_content) => {
-                                        Some(format!("{}\n{}", original_content, content))
+                                *existing_content = match existing_content.take() {
+                                    Some(mut original_content) => {
+                                        original_content.push(b'\n');
+                                        original_content.extend_from_slice(content);
+                                        Some(original_content)
                                     }
                                     None => Some(content.clone()),
                                 };
@@ -80,8 +228,10 @@ impl Generation {
                         if !file_exists {
                             files.push(File {
                                 path: path.to_path_buf(),
-                                content: Some(content.to_string()),
+                                hash: None,
+                                permissions: Permissions::default(),
                             });
+                            contents.push(Some(content.clone()));
                         }
                     }
                     lua::models::File::Delete { path } => {
@@ -96,41 +246,172 @@ impl Generation {
 
                         files.push(File {
                             path: path.to_path_buf(),
-                            content: None,
+                            hash: None,
+                            permissions: Permissions::default(),
                         });
+                        contents.push(None);
                     }
                 },
             }
         }
 
+        for (file, content) in files.iter_mut().zip(contents) {
+            file.hash = content.map(|content| blobs.add(content));
+        }
+
         Ok(Self {
             id,
             creation_datetime: *creation_datetime,
+            profile: config.profile.clone(),
             files,
+            directories,
+            links,
+            packages,
+            services,
+            hooks,
             scripts,
         })
     }
 
-    pub fn write(&self, path: &PathBuf) -> io::Result<()> {
+    pub fn write(&self, path: &Path) -> io::Result<()> {
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent)?;
         }
-        let file = fs::File::create(path)?;
-
-        bincode::serialize_into(file, &self)
-            .map_err(|err| io::Error::new(io::ErrorKind::Other, format!("Bincode error: {}", err)))
+        filesystem::write_atomically(path, &format::encode(self)?)
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct File {
     pub path: PathBuf,
-    pub content: Option<String>,
+    /// Hash of the content in the store, or `None` when the file must not exist.
+    pub hash: Option<String>,
+    pub permissions: Permissions,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Link {
+    pub target: PathBuf,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Package {
+    pub name: String,
+    pub manager: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Service {
+    /// Full unit name, e.g. `nginx.service`.
+    pub name: String,
+    /// Whether the unit is enabled at boot, or `None` to leave it as it is.
+    pub enabled: Option<bool>,
+    /// Whether the unit is running, or `None` to leave it as it is.
+    pub running: Option<bool>,
+    /// Files whose changes restart the unit, or restart it if it runs when its state is left
+    /// alone.
+    pub restart_on: Vec<PathBuf>,
+}
+
+/// Commands to run when any of the paths, or anything below them, changes.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Hook {
+    pub paths: Vec<PathBuf>,
+    pub commands: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Directory {
+    pub path: PathBuf,
+    pub present: bool,
+    pub permissions: Permissions,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Permissions {
+    pub mode: Option<u32>,
+    pub owner: Option<String>,
+    pub group: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct Script {
+    pub name: Option<String>,
     pub install: Vec<String>,
     pub update: Vec<String>,
     pub uninstall: Vec<String>,
 }
+
+impl Script {
+    /// Named scripts are identified by their name, unnamed scripts by their install commands.
+    pub fn is_same_script(&self, other: &Script) -> bool {
+        match (&self.name, &other.name) {
+            (Some(name), Some(other_name)) => name == other_name,
+            (None, None) => self.install == other.install,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct RetentionPolicy {
+    pub keep_last: Option<usize>,
+    pub keep_since: Option<DateTime<Local>>,
+}
+
+impl RetentionPolicy {
+    /// Number of generations `clean` keeps when no rule is given.
+    pub const DEFAULT_KEEP_LAST: usize = 5;
+
+    /// Generations kept by any of the configured rules are retained. Without any rule, only
+    /// the active generation is retained.
+    pub fn is_retained(&self, generation: &Generation, newer_generation_count: usize) -> bool {
+        if let Some(keep_last) = self.keep_last {
+            if newer_generation_count < keep_last {
+                return true;
+            }
+        }
+
+        if let Some(keep_since) = self.keep_since {
+            if generation.creation_datetime >= keep_since {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Parses an age such as `30d`, `12h` or `2w`.
+    pub fn parse_age(age: &str) -> Result<TimeDelta, String> {
+        let invalid_age = || format!("Invalid age: {}", age);
+
+        let unit_index = age
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid_age)?;
+        let (amount, unit) = age.split_at(unit_index);
+        let amount = amount.parse::<i64>().map_err(|_| invalid_age())?;
+
+        match unit {
+            "m" => TimeDelta::try_minutes(amount),
+            "h" => TimeDelta::try_hours(amount),
+            "d" => TimeDelta::try_days(amount),
+            "w" => TimeDelta::try_weeks(amount),
+            _ => None,
+        }
+        .ok_or_else(invalid_age)
+    }
+
+    /// Parses a date such as `2024-11-20` as local midnight, or a full RFC 3339 datetime.
+    pub fn parse_date(date: &str) -> Result<DateTime<Local>, String> {
+        if let Ok(datetime) = DateTime::parse_from_rfc3339(date) {
+            return Ok(datetime.with_timezone(&Local));
+        }
+
+        NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .and_then(|datetime| datetime.and_local_timezone(Local).earliest())
+            .ok_or_else(|| format!("Invalid date: {}", date))
+    }
+}
@@ -1,10 +1,11 @@
-use std::path::PathBuf;
+use std::{fs, path::PathBuf};
 
-use chrono::Local;
+use chrono::{Local, NaiveDate, TimeDelta};
 
-use crate::generations;
-use crate::generations::models::{File, Generation, Script};
+use crate::generations::models::{File, Generation, Permissions, RetentionPolicy, Script, Service};
+use crate::generations::{self, format};
 use crate::lua;
+use crate::store::{self, models::Blobs};
 
 #[test]
 fn generation_read_write() {
@@ -13,43 +14,191 @@ fn generation_read_write() {
 
     let generation = Generation {
         id: 1,
-        creation_datetime: Local::now(),
+        profile: Some(String::from("workstation")),
         files: vec![File {
             path: PathBuf::from("/etc/neovim"),
-            content: Some(String::from("Hello World")),
+            hash: Some(store::hash(b"Hello World")),
+            permissions: Permissions::default(),
         }],
         scripts: vec![Script {
+            name: None,
             install: vec![String::from("sudo apt-get install neovim")],
             update: vec![],
             uninstall: vec![String::from("sudo apt-get uninstall neovim")],
         }],
+        ..Generation::new()
     };
 
     generation.write(&path).unwrap();
 
-    assert_eq!(generation, Generation::from_file(&path).unwrap())
+    assert_eq!(
+        generation,
+        Generation::from_file(&path, &mut Blobs::default()).unwrap()
+    )
+}
+
+#[test]
+fn generation_from_legacy_file() {
+    let storage_directory = assert_fs::TempDir::new().unwrap();
+    let path = storage_directory.join("carbide-0");
+    let creation_datetime = Local::now();
+
+    let legacy_generation = format::v0::Generation {
+        id: 0,
+        creation_datetime,
+        files: vec![format::v0::File {
+            path: PathBuf::from("/etc/neovim"),
+            content: Some(String::from("Hello World")),
+        }],
+        scripts: vec![format::v0::Script {
+            install: vec![String::from("sudo apt-get install neovim")],
+            update: vec![],
+            uninstall: vec![],
+        }],
+    };
+
+    fs::write(&path, bincode::serialize(&legacy_generation).unwrap()).unwrap();
+
+    let mut blobs = Blobs::default();
+    assert_eq!(
+        Generation::from_file(&path, &mut blobs).unwrap(),
+        Generation {
+            id: 0,
+            creation_datetime,
+            files: vec![File {
+                path: PathBuf::from("/etc/neovim"),
+                hash: Some(store::hash(b"Hello World")),
+                permissions: Permissions::default(),
+            }],
+            scripts: vec![Script {
+                name: None,
+                install: vec![String::from("sudo apt-get install neovim")],
+                update: vec![],
+                uninstall: vec![],
+            }],
+            ..Generation::new()
+        }
+    );
+    assert_eq!(
+        blobs
+            .read(storage_directory.path(), &store::hash(b"Hello World"))
+            .unwrap(),
+        b"Hello World"
+    );
+    assert!(!storage_directory.join("store").exists());
+}
+
+#[test]
+fn generation_from_previous_version_file() {
+    let storage_directory = assert_fs::TempDir::new().unwrap();
+    let path = storage_directory.join("carbide-0");
+    let creation_datetime = Local::now();
+
+    let previous_generation = format::v5::Generation {
+        id: 0,
+        creation_datetime,
+        profile: Some(String::from("workstation")),
+        files: vec![format::v2::File {
+            path: PathBuf::from("/etc/neovim"),
+            hash: Some(store::hash(b"Hello World")),
+            permissions: format::v1::Permissions {
+                mode: Some(0o644),
+                owner: None,
+                group: None,
+            },
+        }],
+        directories: vec![],
+        links: vec![],
+        packages: vec![],
+        services: vec![format::v5::Service {
+            name: String::from("nginx.service"),
+            enabled: Some(true),
+            running: None,
+            restart_on: vec![PathBuf::from("/etc/nginx/nginx.conf")],
+        }],
+        scripts: vec![],
+    };
+
+    let payload = bincode::serialize(&previous_generation).unwrap();
+    let mut content = format::MAGIC.to_vec();
+    content.extend_from_slice(&5u32.to_le_bytes());
+    content.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    content.extend_from_slice(&payload);
+    fs::write(&path, content).unwrap();
+
+    assert_eq!(
+        Generation::from_file(&path, &mut Blobs::default()).unwrap(),
+        Generation {
+            id: 0,
+            creation_datetime,
+            profile: Some(String::from("workstation")),
+            files: vec![File {
+                path: PathBuf::from("/etc/neovim"),
+                hash: Some(store::hash(b"Hello World")),
+                permissions: Permissions {
+                    mode: Some(0o644),
+                    owner: None,
+                    group: None,
+                },
+            }],
+            services: vec![Service {
+                name: String::from("nginx.service"),
+                enabled: Some(true),
+                running: None,
+                restart_on: vec![PathBuf::from("/etc/nginx/nginx.conf")],
+            }],
+            ..Generation::new()
+        }
+    );
+}
+
+#[test]
+fn generation_from_corrupt_file() {
+    let storage_directory = assert_fs::TempDir::new().unwrap();
+    let path = storage_directory.join("carbide-0");
+
+    Generation::new().write(&path).unwrap();
+
+    let mut content = fs::read(&path).unwrap();
+    let last = content.len() - 1;
+    content[last] ^= 0xff;
+    fs::write(&path, &content).unwrap();
+
+    let err = Generation::from_file(&path, &mut Blobs::default()).unwrap_err();
+    assert!(err.to_string().contains("checksum mismatch"));
+
+    content[4..8].copy_from_slice(&(format::CURRENT_VERSION + 1).to_le_bytes());
+    fs::write(&path, &content).unwrap();
+
+    let err = Generation::from_file(&path, &mut Blobs::default()).unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Unsupported generation format version"));
 }
 
 #[test]
 fn generation_from_lua_config() {
     let config = lua::models::Config {
+        profile: None,
         actions: vec![
             lua::models::Action::Script(lua::models::Script {
+                name: None,
                 install: vec![String::from("sudo apt-get install carbide")],
                 update: vec![],
                 uninstall: vec![String::from("sudo apt-get uninstall carbide")],
             }),
             lua::models::Action::File(lua::models::File::Set {
                 path: PathBuf::from("/file_set"),
-                content: String::from("print(\"Hello World\")"),
+                content: b"print(\"Hello World\")".to_vec(),
+                permissions: lua::models::Permissions::default(),
             }),
             lua::models::Action::File(lua::models::File::Append {
                 path: PathBuf::from("/file_append"),
-                content: String::from("print(\"Hello World\")"),
+                content: b"print(\"Hello World\")".to_vec(),
             }),
             lua::models::Action::File(lua::models::File::Append {
                 path: PathBuf::from("/file_append"),
-                content: String::from("print(\"Hello World 2\")"),
+                content: b"print(\"Hello World 2\")".to_vec(),
             }),
             lua::models::Action::File(lua::models::File::Delete {
                 path: PathBuf::from("/file_delete"),
@@ -57,8 +206,11 @@ fn generation_from_lua_config() {
         ],
     };
 
+    let data_directory = assert_fs::TempDir::new().unwrap();
     let creation_datetime = Local::now();
-    let generation = Generation::from_lua_config(&config, 0, &creation_datetime).unwrap();
+    let mut blobs = Blobs::default();
+    let generation =
+        Generation::from_lua_config(&config, 0, &creation_datetime, &mut blobs).unwrap();
 
     assert_eq!(
         generation,
@@ -68,26 +220,87 @@ fn generation_from_lua_config() {
             files: vec![
                 File {
                     path: PathBuf::from("/file_set"),
-                    content: Some(String::from("print(\"Hello World\")"))
+                    hash: Some(store::hash(b"print(\"Hello World\")")),
+                    permissions: Permissions::default(),
                 },
                 File {
                     path: PathBuf::from("/file_append"),
-                    content: Some(String::from(
-                        "print(\"Hello World\")\nprint(\"Hello World 2\")"
-                    ))
+                    hash: Some(store::hash(
+                        b"print(\"Hello World\")\nprint(\"Hello World 2\")"
+                    )),
+                    permissions: Permissions::default(),
                 },
                 File {
                     path: PathBuf::from("/file_delete"),
-                    content: None
+                    hash: None,
+                    permissions: Permissions::default(),
                 },
             ],
             scripts: vec![Script {
+                name: None,
                 install: vec![String::from("sudo apt-get install carbide")],
                 update: vec![],
                 uninstall: vec![String::from("sudo apt-get uninstall carbide")],
-            }]
+            }],
+            ..Generation::new()
         }
-    )
+    );
+    assert_eq!(
+        blobs
+            .read(
+                data_directory.path(),
+                &store::hash(b"print(\"Hello World\")")
+            )
+            .unwrap(),
+        b"print(\"Hello World\")"
+    );
+    assert!(!data_directory.join("store").exists());
+}
+
+#[test]
+fn generation_from_lua_config_service() {
+    let service = lua::models::Service {
+        name: String::from("nginx.service"),
+        unit: Some(b"[Service]".to_vec()),
+        enabled: Some(true),
+        running: None,
+        restart_on: vec![],
+    };
+    let config = lua::models::Config {
+        profile: None,
+        actions: vec![lua::models::Action::Service(service.clone())],
+    };
+
+    let mut blobs = Blobs::default();
+    let generation = Generation::from_lua_config(&config, 0, &Local::now(), &mut blobs).unwrap();
+
+    assert_eq!(
+        generation.files,
+        vec![File {
+            path: PathBuf::from("/etc/systemd/system/nginx.service"),
+            hash: Some(store::hash(b"[Service]")),
+            permissions: Permissions::default(),
+        }]
+    );
+    assert_eq!(
+        generation.services,
+        vec![Service {
+            name: String::from("nginx.service"),
+            enabled: Some(true),
+            running: None,
+            restart_on: vec![],
+        }]
+    );
+
+    let config = lua::models::Config {
+        profile: None,
+        actions: vec![
+            lua::models::Action::Service(service.clone()),
+            lua::models::Action::Service(service),
+        ],
+    };
+
+    assert!(Generation::from_lua_config(&config, 0, &Local::now(), &mut blobs).is_err());
 }
 
 #[test]
@@ -95,13 +308,13 @@ fn read_generations() {
     let storage_directory = assert_fs::TempDir::new().unwrap();
     let generation_0 = Generation {
         id: 0,
-        creation_datetime: Local::now(),
-        files: vec![],
         scripts: vec![Script {
+            name: None,
             install: vec![String::from("sudo apt-get install neovim")],
             update: vec![],
             uninstall: vec![String::from("sudo apt-get uninstall neovim")],
         }],
+        ..Generation::new()
     };
 
     generation_0
@@ -110,16 +323,18 @@ fn read_generations() {
 
     let generation_1 = Generation {
         id: 1,
-        creation_datetime: Local::now(),
         files: vec![File {
             path: PathBuf::from("/etc/neovim"),
-            content: Some(String::from("Hello World")),
+            hash: Some(store::hash(b"Hello World")),
+            permissions: Permissions::default(),
         }],
         scripts: vec![Script {
+            name: None,
             install: vec![String::from("sudo apt-get install neovim")],
             update: vec![],
             uninstall: vec![String::from("sudo apt-get uninstall neovim")],
         }],
+        ..Generation::new()
     };
 
     generation_1
@@ -127,23 +342,47 @@ fn read_generations() {
         .unwrap();
 
     assert_eq!(
-        generations::read_generations(&storage_directory.to_path_buf()).unwrap(),
+        generations::read_generations(&storage_directory).unwrap(),
         vec![generation_0, generation_1]
     )
 }
 
 #[test]
-fn read_last_generation() {
+fn read_generations_skips_unreadable_files() {
+    let storage_directory = assert_fs::TempDir::new().unwrap();
+    let generation = Generation {
+        id: 1,
+        ..Generation::new()
+    };
+
+    generation
+        .write(&storage_directory.join("carbide-1"))
+        .unwrap();
+    fs::write(storage_directory.join("carbide-0"), "Hello World").unwrap();
+
+    assert_eq!(
+        generations::read_generation_ids(storage_directory.path()).unwrap(),
+        vec![0, 1]
+    );
+    assert_eq!(
+        generations::read_generations(&storage_directory).unwrap(),
+        vec![generation]
+    );
+    assert!(generations::read_generation(storage_directory.path(), 0).is_err());
+}
+
+#[test]
+fn read_current_generation() {
     let storage_directory = assert_fs::TempDir::new().unwrap();
     let generation_0 = Generation {
         id: 0,
-        creation_datetime: Local::now(),
-        files: vec![],
         scripts: vec![Script {
+            name: None,
             install: vec![String::from("sudo apt-get install neovim")],
             update: vec![],
             uninstall: vec![String::from("sudo apt-get uninstall neovim")],
         }],
+        ..Generation::new()
     };
 
     generation_0
@@ -152,24 +391,173 @@ fn read_last_generation() {
 
     let generation_1 = Generation {
         id: 1,
-        creation_datetime: Local::now(),
         files: vec![File {
             path: PathBuf::from("/etc/neovim"),
-            content: Some(String::from("Hello World")),
+            hash: Some(store::hash(b"Hello World")),
+            permissions: Permissions::default(),
         }],
         scripts: vec![Script {
+            name: None,
             install: vec![String::from("sudo apt-get install neovim")],
             update: vec![],
             uninstall: vec![String::from("sudo apt-get uninstall neovim")],
         }],
+        ..Generation::new()
     };
 
     generation_1
         .write(&storage_directory.join("carbide-1"))
         .unwrap();
 
+    generations::write_current_generation_id(&storage_directory, 0).unwrap();
+
     assert_eq!(
-        generations::read_last_generation(&storage_directory.to_path_buf()).unwrap(),
-        generation_1
-    )
+        generations::read_current_generation(&storage_directory).unwrap(),
+        generation_0
+    );
+    assert_eq!(
+        generations::next_generation_id(&storage_directory).unwrap(),
+        2
+    );
+}
+
+#[test]
+fn read_current_generation_without_marker() {
+    let storage_directory = assert_fs::TempDir::new().unwrap();
+
+    assert_eq!(
+        generations::read_current_generation(&storage_directory)
+            .unwrap_err()
+            .kind(),
+        std::io::ErrorKind::NotFound
+    );
+    assert_eq!(
+        generations::next_generation_id(&storage_directory).unwrap(),
+        0
+    );
+
+    // Data directories written before the marker existed fall back to the newest generation.
+    for id in [0, 1] {
+        let legacy_generation = format::v0::Generation {
+            id,
+            creation_datetime: Local::now(),
+            files: vec![],
+            scripts: vec![],
+        };
+        fs::write(
+            storage_directory.join(format!("carbide-{}", id)),
+            bincode::serialize(&legacy_generation).unwrap(),
+        )
+        .unwrap();
+    }
+
+    assert_eq!(
+        generations::read_current_generation(&storage_directory)
+            .unwrap()
+            .id,
+        1
+    );
+
+    // A generation written since then without a marker was never applied.
+    Generation {
+        id: 2,
+        ..Generation::new()
+    }
+    .write(&storage_directory.join("carbide-2"))
+    .unwrap();
+
+    assert_eq!(
+        generations::read_current_generation(&storage_directory)
+            .unwrap_err()
+            .kind(),
+        std::io::ErrorKind::NotFound
+    );
+}
+
+#[test]
+fn expired_generations() {
+    let now = Local::now();
+    let generations: Vec<Generation> = (0..5)
+        .map(|id| Generation {
+            id,
+            creation_datetime: now - TimeDelta::days(10 * (4 - id as i64)),
+            ..Generation::new()
+        })
+        .collect();
+
+    let expired_ids = |active_id: i32, policy: &RetentionPolicy| -> Vec<i32> {
+        generations::expired_generations(&generations, active_id, policy)
+            .iter()
+            .map(|generation| generation.id)
+            .collect()
+    };
+
+    assert_eq!(
+        expired_ids(4, &RetentionPolicy::default()),
+        vec![0, 1, 2, 3]
+    );
+    assert_eq!(
+        expired_ids(1, &RetentionPolicy::default()),
+        vec![0, 2, 3, 4]
+    );
+    // Without a known active generation the newest one is kept.
+    assert_eq!(
+        expired_ids(-1, &RetentionPolicy::default()),
+        vec![0, 1, 2, 3]
+    );
+    assert_eq!(
+        expired_ids(
+            4,
+            &RetentionPolicy {
+                keep_last: Some(2),
+                keep_since: None,
+            }
+        ),
+        vec![0, 1, 2]
+    );
+    assert_eq!(
+        expired_ids(
+            4,
+            &RetentionPolicy {
+                keep_last: None,
+                keep_since: Some(now - TimeDelta::days(25)),
+            }
+        ),
+        vec![0, 1]
+    );
+    assert_eq!(
+        expired_ids(
+            4,
+            &RetentionPolicy {
+                keep_last: Some(4),
+                keep_since: Some(now - TimeDelta::days(25)),
+            }
+        ),
+        vec![0]
+    );
+}
+
+#[test]
+fn retention_policy_parse_age() {
+    assert_eq!(RetentionPolicy::parse_age("30d"), Ok(TimeDelta::days(30)));
+    assert_eq!(RetentionPolicy::parse_age("12h"), Ok(TimeDelta::hours(12)));
+    assert_eq!(RetentionPolicy::parse_age("2w"), Ok(TimeDelta::weeks(2)));
+    assert!(RetentionPolicy::parse_age("30").is_err());
+    assert!(RetentionPolicy::parse_age("d").is_err());
+    assert!(RetentionPolicy::parse_age("30y").is_err());
+}
+
+#[test]
+fn retention_policy_parse_date() {
+    assert_eq!(
+        RetentionPolicy::parse_date("2024-11-20").unwrap(),
+        NaiveDate::from_ymd_opt(2024, 11, 20)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_local_timezone(Local)
+            .unwrap()
+    );
+    assert!(RetentionPolicy::parse_date("2024-11-20T10:00:00+00:00").is_ok());
+    assert!(RetentionPolicy::parse_date("yesterday").is_err());
 }
@@ -1,31 +1,82 @@
 use std::{
-    fs,
-    path::PathBuf,
+    fs, io,
+    path::{Component, Path, PathBuf},
     sync::{Arc, Mutex},
 };
 
-use mlua::{Lua, LuaOptions, Result, StdLib, Table};
+use mlua::{Either, Function, Lua, LuaOptions, Result, StdLib, Table, Value};
+
+use crate::{facts, packages, services};
 
 pub mod models;
+mod template;
 #[cfg(test)]
 mod tests;
 
-pub fn parse_config(directory: &PathBuf) -> Result<models::Config> {
+/// Evaluates `hosts/<profile>.lua` when a profile is selected and that file exists, and
+/// `init.lua` otherwise, in which case `carbide.profile` is still set but no profile is
+/// recorded.
+pub fn parse_config(
+    directory: &Path,
+    profile: Option<&str>,
+    collector: &dyn facts::Collector,
+) -> Result<models::Config> {
+    let mut entry_point = PathBuf::from("init.lua");
+    if let Some(profile) = profile {
+        if profile.is_empty() || profile.starts_with('.') || profile.contains('/') {
+            return Err(mlua::Error::runtime(format!(
+                "Invalid profile name: {}",
+                profile
+            )));
+        }
+
+        let profile_entry_point = Path::new("hosts").join(format!("{}.lua", profile));
+        if directory.join(&profile_entry_point).is_file() {
+            entry_point = profile_entry_point;
+        }
+    }
+
     let actions = Arc::new(Mutex::new(Vec::<models::Action>::new()));
 
-    let mlua = Lua::new_with(StdLib::PACKAGE, LuaOptions::new())?;
+    // Only libraries without access to the system are loaded, the config reads files through
+    // `carbide.fs` instead.
+    let mlua = Lua::new_with(
+        StdLib::PACKAGE | StdLib::STRING | StdLib::TABLE | StdLib::MATH | StdLib::UTF8,
+        LuaOptions::new(),
+    )?;
     let carbide_table = mlua.create_table()?;
+    let facts = collector.collect()?;
 
     let file_table = mlua.create_table()?;
 
     let actions_clone = Arc::clone(&actions);
     file_table.set(
         "set",
-        mlua.create_function(move |_, (path, content): (String, String)| {
+        mlua.create_function(
+            move |lua, (path, content, options): (String, mlua::String, Option<Table>)| {
+                let (permissions, on_change) = file_options(lua, options)?;
+
+                let mut actions = actions_clone.lock().unwrap();
+                actions.push(models::Action::File(models::File::Set {
+                    path: PathBuf::from(&path),
+                    content: content.as_bytes().to_vec(),
+                    permissions,
+                }));
+                push_hook(&mut actions, PathBuf::from(path), on_change);
+
+                Ok(())
+            },
+        )?,
+    )?;
+
+    let actions_clone = Arc::clone(&actions);
+    file_table.set(
+        "append",
+        mlua.create_function(move |_, (path, content): (String, mlua::String)| {
             let mut actions = actions_clone.lock().unwrap();
-            actions.push(models::Action::File(models::File::Set {
+            actions.push(models::Action::File(models::File::Append {
                 path: PathBuf::from(path),
-                content,
+                content: content.as_bytes().to_vec(),
             }));
 
             Ok(())
@@ -34,12 +85,11 @@ pub fn parse_config(directory: &PathBuf) -> Result<models::Config> {
 
     let actions_clone = Arc::clone(&actions);
     file_table.set(
-        "append",
-        mlua.create_function(move |_, (path, content): (String, String)| {
+        "delete",
+        mlua.create_function(move |_, path: String| {
             let mut actions = actions_clone.lock().unwrap();
-            actions.push(models::Action::File(models::File::Append {
+            actions.push(models::Action::File(models::File::Delete {
                 path: PathBuf::from(path),
-                content,
             }));
 
             Ok(())
@@ -47,11 +97,170 @@ pub fn parse_config(directory: &PathBuf) -> Result<models::Config> {
     )?;
 
     let actions_clone = Arc::clone(&actions);
+    let config_directory = directory.to_path_buf();
+    file_table.set(
+        "source",
+        mlua.create_function(
+            move |lua, (path, source, options): (String, String, Option<Table>)| {
+                let (permissions, on_change) = file_options(lua, options)?;
+                let source = resolve_config_path(&config_directory, &source)?;
+                let content = fs::read(&source).map_err(|err| {
+                    mlua::Error::runtime(format!("Error reading {}: {}", source.display(), err))
+                })?;
+
+                let mut actions = actions_clone.lock().unwrap();
+                actions.push(models::Action::File(models::File::Set {
+                    path: PathBuf::from(&path),
+                    content,
+                    permissions,
+                }));
+                push_hook(&mut actions, PathBuf::from(path), on_change);
+
+                Ok(())
+            },
+        )?,
+    )?;
+
+    let actions_clone = Arc::clone(&actions);
+    let config_directory = directory.to_path_buf();
+    file_table.set(
+        "tree",
+        mlua.create_function(
+            move |lua, (path, source, options): (String, String, Option<Table>)| {
+                let (permissions, on_change) = file_options(lua, options)?;
+                let source = resolve_config_path(&config_directory, &source)?;
+                let mut files = Vec::new();
+                read_tree(&source, &PathBuf::from(&path), &mut files).map_err(|err| {
+                    mlua::Error::runtime(format!("Error reading {}: {}", source.display(), err))
+                })?;
+
+                let mut contents = Vec::new();
+                for (path, source) in files {
+                    // Symlinked files must not lead outside the config directory either.
+                    let source = resolve_config_path(&config_directory, &source.to_string_lossy())?;
+                    let content = fs::read(&source).map_err(|err| {
+                        mlua::Error::runtime(format!("Error reading {}: {}", source.display(), err))
+                    })?;
+
+                    contents.push((path, content));
+                }
+
+                let mut actions = actions_clone.lock().unwrap();
+                for (path, content) in contents {
+                    actions.push(models::Action::File(models::File::Set {
+                        path,
+                        content,
+                        permissions: permissions.clone(),
+                    }));
+                }
+                // Any change below the destination triggers the hook.
+                push_hook(&mut actions, PathBuf::from(path), on_change);
+
+                Ok(())
+            },
+        )?,
+    )?;
+
+    let actions_clone = Arc::clone(&actions);
+    let config_directory = directory.to_path_buf();
     file_table.set(
+        "template",
+        mlua.create_function(
+            move |lua,
+                  (path, template, variables, options): (
+                String,
+                Table,
+                template::Value,
+                Option<Table>,
+            )| {
+                let (permissions, on_change) = file_options(lua, options)?;
+
+                let (name, template) = match (
+                    template.get::<Option<String>>("source")?,
+                    template.get::<Option<String>>("text")?,
+                ) {
+                    (Some(source), None) => {
+                        let path = resolve_config_path(&config_directory, &source)?;
+                        let content = fs::read_to_string(&path).map_err(|err| {
+                            mlua::Error::runtime(format!(
+                                "Error reading {}: {}",
+                                path.display(),
+                                err
+                            ))
+                        })?;
+
+                        (source, content)
+                    }
+                    (None, Some(text)) => (path.clone(), text),
+                    _ => {
+                        return Err(mlua::Error::runtime(format!(
+                            "Template for {} needs either a source or a text",
+                            path
+                        )))
+                    }
+                };
+
+                let content =
+                    template::render(&name, &template, &variables).map_err(mlua::Error::runtime)?;
+
+                let mut actions = actions_clone.lock().unwrap();
+                actions.push(models::Action::File(models::File::Set {
+                    path: PathBuf::from(&path),
+                    content: content.into_bytes(),
+                    permissions,
+                }));
+                push_hook(&mut actions, PathBuf::from(path), on_change);
+
+                Ok(())
+            },
+        )?,
+    )?;
+
+    carbide_table.set("file", file_table)?;
+
+    let actions_clone = Arc::clone(&actions);
+    carbide_table.set(
+        "on_change",
+        mlua.create_function(
+            move |_, (paths, commands): (Either<String, Vec<String>>, Either<String, Vec<String>>)| {
+                let paths = paths.map_left(|path| vec![path]).into_inner();
+                let commands = commands.map_left(|command| vec![command]).into_inner();
+
+                let mut actions = actions_clone.lock().unwrap();
+                actions.push(models::Action::Hook(models::Hook {
+                    paths: paths.into_iter().map(PathBuf::from).collect(),
+                    commands,
+                }));
+
+                Ok(())
+            },
+        )?,
+    )?;
+
+    let directory_table = mlua.create_table()?;
+
+    let actions_clone = Arc::clone(&actions);
+    directory_table.set(
+        "create",
+        mlua.create_function(
+            move |_, (path, permissions): (String, models::Permissions)| {
+                let mut actions = actions_clone.lock().unwrap();
+                actions.push(models::Action::Directory(models::Directory::Create {
+                    path: PathBuf::from(path),
+                    permissions,
+                }));
+
+                Ok(())
+            },
+        )?,
+    )?;
+
+    let actions_clone = Arc::clone(&actions);
+    directory_table.set(
         "delete",
         mlua.create_function(move |_, path: String| {
             let mut actions = actions_clone.lock().unwrap();
-            actions.push(models::Action::File(models::File::Delete {
+            actions.push(models::Action::Directory(models::Directory::Delete {
                 path: PathBuf::from(path),
             }));
 
@@ -59,15 +268,112 @@ pub fn parse_config(directory: &PathBuf) -> Result<models::Config> {
         })?,
     )?;
 
-    carbide_table.set("file", file_table)?;
+    carbide_table.set("directory", directory_table)?;
+
+    let actions_clone = Arc::clone(&actions);
+    let default_package_manager = packages::default_package_manager(&facts.distro);
+    carbide_table.set(
+        "package",
+        mlua.create_function(move |_, (name, options): (String, Option<Table>)| {
+            if !packages::is_valid_package_name(&name) {
+                return Err(mlua::Error::runtime(format!(
+                    "Invalid package name: {}",
+                    name
+                )));
+            }
+
+            let manager = match options {
+                Some(options) => options.get::<Option<String>>("manager")?,
+                None => None,
+            };
+            let manager = manager
+                .or_else(|| default_package_manager.map(String::from))
+                .ok_or_else(|| {
+                    mlua::Error::runtime(format!(
+                        "No package manager given for {} and none known for this distro",
+                        name
+                    ))
+                })?;
+
+            let mut actions = actions_clone.lock().unwrap();
+            actions.push(models::Action::Package(models::Package { name, manager }));
+
+            Ok(())
+        })?,
+    )?;
+
+    let actions_clone = Arc::clone(&actions);
+    carbide_table.set(
+        "service",
+        mlua.create_function(move |_, options: Table| {
+            let name: String = options.get("name")?;
+            if !services::is_valid_unit_name(&name) {
+                return Err(mlua::Error::runtime(format!(
+                    "Invalid service name: {}",
+                    name
+                )));
+            }
+
+            let running = match options.get::<Option<String>>("state")?.as_deref() {
+                Some("running") => Some(true),
+                Some("stopped") => Some(false),
+                Some(state) => {
+                    return Err(mlua::Error::runtime(format!(
+                        "Invalid service state for {}: {} (expected running or stopped)",
+                        name, state
+                    )))
+                }
+                None => None,
+            };
+
+            let mut actions = actions_clone.lock().unwrap();
+            actions.push(models::Action::Service(models::Service {
+                name: services::unit_name(&name),
+                unit: options
+                    .get::<Option<mlua::String>>("unit")?
+                    .map(|unit| unit.as_bytes().to_vec()),
+                enabled: options.get("enabled")?,
+                running,
+                restart_on: options
+                    .get::<Option<Vec<String>>>("restart_on")?
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(PathBuf::from)
+                    .collect(),
+            }));
+
+            Ok(())
+        })?,
+    )?;
+
+    let actions_clone = Arc::clone(&actions);
+    carbide_table.set(
+        "link",
+        mlua.create_function(move |_, (target, path): (String, String)| {
+            let mut actions = actions_clone.lock().unwrap();
+            actions.push(models::Action::Link(models::Link {
+                target: PathBuf::from(target),
+                path: PathBuf::from(path),
+            }));
+
+            Ok(())
+        })?,
+    )?;
 
     let actions_clone = Arc::clone(&actions);
     carbide_table.set(
         "script",
         mlua.create_function(
-            move |_, (install, update, uninstall): (Vec<String>, Vec<String>, Vec<String>)| {
+            move |_,
+                  (install, update, uninstall, name): (
+                Vec<String>,
+                Vec<String>,
+                Vec<String>,
+                Option<String>,
+            )| {
                 let mut actions = actions_clone.lock().unwrap();
                 actions.push(models::Action::Script(models::Script {
+                    name,
                     install,
                     update,
                     uninstall,
@@ -78,6 +384,12 @@ pub fn parse_config(directory: &PathBuf) -> Result<models::Config> {
         )?,
     )?;
 
+    carbide_table.set("fs", create_fs_table(&mlua, directory)?)?;
+    carbide_table.set("profile", profile)?;
+
+    let facts = create_facts_table(&mlua, &facts)?;
+    carbide_table.set("facts", read_only_table(&mlua, facts)?)?;
+
     mlua.globals().set("carbide", carbide_table)?;
 
     let package: Table = mlua.globals().get("package")?;
@@ -89,21 +401,280 @@ pub fn parse_config(directory: &PathBuf) -> Result<models::Config> {
             directory
                 .join("?.lua")
                 .to_str()
-                .expect("Invalid config path")
-                .to_string(),
+                .expect("Invalid config path"),
             package_path
         ),
     )?;
 
     let init_script = fs::read_to_string(
         directory
-            .join("init.lua")
+            .join(&entry_point)
             .to_str()
-            .expect("Invalid init script path")
-            .to_string(),
+            .expect("Invalid init script path"),
     )?;
-    mlua.load(init_script).set_name("init.lua").exec()?;
+    mlua.load(init_script)
+        .set_name(entry_point.to_string_lossy())
+        .exec()?;
 
     let actions = actions.lock().unwrap().clone();
-    Ok(models::Config { actions })
+    // The selected profile is still exposed to `init.lua`, but the config was not built for
+    // it.
+    Ok(models::Config {
+        profile: profile
+            .filter(|_| entry_point != Path::new("init.lua"))
+            .map(String::from),
+        actions,
+    })
+}
+
+/// Read-only access to files in the config directory.
+fn create_fs_table(lua: &Lua, directory: &Path) -> Result<Table> {
+    let table = lua.create_table()?;
+
+    let config_directory = directory.to_path_buf();
+    table.set(
+        "read",
+        lua.create_function(move |lua, path: String| {
+            let path = resolve_config_path(&config_directory, &path)?;
+            let content = fs::read(&path).map_err(|err| {
+                mlua::Error::runtime(format!("Error reading {}: {}", path.display(), err))
+            })?;
+
+            lua.create_string(content)
+        })?,
+    )?;
+
+    let config_directory = directory.to_path_buf();
+    table.set(
+        "exists",
+        lua.create_function(move |_, path: String| {
+            Ok(resolve_config_path(&config_directory, &path)?.exists())
+        })?,
+    )?;
+
+    let config_directory = directory.to_path_buf();
+    table.set(
+        "list",
+        lua.create_function(move |_, path: String| {
+            let path = resolve_config_path(&config_directory, &path)?;
+            let mut names = fs::read_dir(&path)
+                .and_then(|entries| {
+                    entries
+                        .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+                        .collect::<io::Result<Vec<_>>>()
+                })
+                .map_err(|err| {
+                    mlua::Error::runtime(format!("Error listing {}: {}", path.display(), err))
+                })?;
+            names.sort();
+
+            Ok(names)
+        })?,
+    )?;
+
+    let config_directory = directory.to_path_buf();
+    table.set(
+        "glob",
+        lua.create_function(move |_, pattern: String| {
+            resolve_config_path(&config_directory, &pattern)?;
+            let full_pattern = format!(
+                "{}/{}",
+                glob::Pattern::escape(&config_directory.to_string_lossy()),
+                pattern
+            );
+            let paths = glob::glob(&full_pattern)
+                .map_err(|err| {
+                    mlua::Error::runtime(format!("Invalid glob pattern {}: {}", pattern, err))
+                })?
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(|err| {
+                    mlua::Error::runtime(format!("Error matching {}: {}", pattern, err))
+                })?;
+
+            let mut matches = Vec::new();
+            for path in paths {
+                // Skips matches that lead outside the config directory through a symlink.
+                if resolve_config_path(&config_directory, &path.to_string_lossy()).is_err() {
+                    continue;
+                }
+
+                if let Ok(relative_path) = path.strip_prefix(&config_directory) {
+                    matches.push(relative_path.to_string_lossy().into_owned());
+                }
+            }
+            matches.sort();
+
+            Ok(matches)
+        })?,
+    )?;
+
+    Ok(table)
+}
+
+/// Resolves a path relative to the config directory, refusing paths that lead outside of it.
+fn resolve_config_path(config_directory: &Path, path: &str) -> Result<PathBuf> {
+    let outside =
+        || mlua::Error::runtime(format!("Path is outside the config directory: {}", path));
+
+    let relative_path = Path::new(path);
+    let relative_path = relative_path
+        .strip_prefix(config_directory)
+        .unwrap_or(relative_path);
+
+    if relative_path
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(outside());
+    }
+
+    let path = config_directory.join(relative_path);
+
+    // Symlinks are followed for paths that exist, so they must not lead outside either.
+    if let (Ok(canonical_path), Ok(canonical_directory)) =
+        (path.canonicalize(), config_directory.canonicalize())
+    {
+        if !canonical_path.starts_with(canonical_directory) {
+            return Err(outside());
+        }
+    }
+
+    Ok(path)
+}
+
+fn create_facts_table(lua: &Lua, facts: &facts::models::Facts) -> Result<Table> {
+    let table = lua.create_table()?;
+
+    table.set("hostname", facts.hostname.as_str())?;
+    table.set("kernel", facts.kernel.as_str())?;
+    table.set("architecture", facts.architecture.as_str())?;
+    table.set("cpu_count", facts.cpu_count)?;
+    table.set("memory", facts.memory)?;
+
+    let distro = lua.create_table()?;
+    distro.set("id", facts.distro.id.as_str())?;
+    distro.set("name", facts.distro.name.as_str())?;
+    distro.set("version", facts.distro.version.as_str())?;
+    table.set("distro", distro)?;
+
+    let users = lua.create_table()?;
+    for user in &facts.users {
+        let user_table = lua.create_table()?;
+        user_table.set("name", user.name.as_str())?;
+        user_table.set("uid", user.uid)?;
+        user_table.set("gid", user.gid)?;
+        user_table.set("home", user.home.as_str())?;
+        user_table.set("shell", user.shell.as_str())?;
+        users.set(user.name.as_str(), user_table)?;
+    }
+    table.set("users", users)?;
+
+    let groups = lua.create_table()?;
+    for group in &facts.groups {
+        let group_table = lua.create_table()?;
+        group_table.set("name", group.name.as_str())?;
+        group_table.set("gid", group.gid)?;
+        group_table.set("members", group.members.clone())?;
+        groups.set(group.name.as_str(), group_table)?;
+    }
+    table.set("groups", groups)?;
+
+    table.set("env", facts.environment.clone())?;
+
+    Ok(table)
+}
+
+/// Wraps a table and every table in it in a proxy that refuses assignments, while still
+/// supporting indexing, `#` and `pairs`.
+fn read_only_table(lua: &Lua, table: Table) -> Result<Table> {
+    let nested_tables = table
+        .pairs::<Value, Value>()
+        .filter_map(|pair| match pair {
+            Ok((key, Value::Table(nested_table))) => Some(Ok((key, nested_table))),
+            Ok(_) => None,
+            Err(err) => Some(Err(err)),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    for (key, nested_table) in nested_tables {
+        table.raw_set(key, read_only_table(lua, nested_table)?)?;
+    }
+
+    let next: Function = lua.globals().get("next")?;
+    let pairs_table = table.clone();
+    let length_table = table.clone();
+
+    let metatable = lua.create_table()?;
+    metatable.set("__index", table)?;
+    metatable.set(
+        "__newindex",
+        lua.create_function(|_, ()| {
+            Err::<(), _>(mlua::Error::runtime("carbide.facts is read-only"))
+        })?,
+    )?;
+    metatable.set(
+        "__pairs",
+        lua.create_function(move |_, ()| Ok((next.clone(), pairs_table.clone(), Value::Nil)))?,
+    )?;
+    metatable.set(
+        "__len",
+        lua.create_function(move |_, ()| Ok(length_table.raw_len()))?,
+    )?;
+    metatable.set("__metatable", false)?;
+
+    let proxy = lua.create_table()?;
+    proxy.set_metatable(Some(metatable));
+
+    Ok(proxy)
+}
+
+/// Collects where every file below `source` goes below `destination` along with its path, in
+/// a stable order. Symlinked directories are skipped, so that they cannot loop or lead outside
+/// the config directory.
+fn read_tree(
+    source: &Path,
+    destination: &Path,
+    files: &mut Vec<(PathBuf, PathBuf)>,
+) -> io::Result<()> {
+    let mut entries = fs::read_dir(source)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let destination = destination.join(entry.file_name());
+
+        let file_type = entry.file_type()?;
+
+        if file_type.is_dir() {
+            read_tree(&path, &destination, files)?;
+        } else if file_type.is_symlink() && path.is_dir() {
+            continue;
+        } else {
+            files.push((destination, path));
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits the options of a file function into its permissions and its `on_change` commands.
+fn file_options(lua: &Lua, options: Option<Table>) -> Result<(models::Permissions, Vec<String>)> {
+    let Some(options) = options else {
+        return Ok((models::Permissions::default(), Vec::new()));
+    };
+
+    let on_change = options
+        .get::<Option<Vec<String>>>("on_change")?
+        .unwrap_or_default();
+
+    Ok((lua.unpack(Value::Table(options))?, on_change))
+}
+
+fn push_hook(actions: &mut Vec<models::Action>, path: PathBuf, commands: Vec<String>) {
+    if !commands.is_empty() {
+        actions.push(models::Action::Hook(models::Hook {
+            paths: vec![path],
+            commands,
+        }));
+    }
 }
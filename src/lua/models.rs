@@ -4,6 +4,8 @@ use mlua::{FromLua, IntoLua, Lua, Value};
 
 #[derive(Debug, PartialEq)]
 pub struct Config {
+    /// The profile whose entry point was evaluated, if one was selected.
+    pub profile: Option<String>,
     pub actions: Vec<Action>,
 }
 
@@ -11,10 +13,16 @@ pub struct Config {
 pub enum Action {
     Script(Script),
     File(File),
+    Directory(Directory),
+    Link(Link),
+    Package(Package),
+    Service(Service),
+    Hook(Hook),
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Script {
+    pub name: Option<String>,
     pub install: Vec<String>,
     pub update: Vec<String>,
     pub uninstall: Vec<String>,
@@ -22,12 +30,115 @@ pub struct Script {
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum File {
-    Set { path: PathBuf, content: String },
-    Append { path: PathBuf, content: String },
-    Delete { path: PathBuf },
+    Set {
+        path: PathBuf,
+        content: Vec<u8>,
+        permissions: Permissions,
+    },
+    Append {
+        path: PathBuf,
+        content: Vec<u8>,
+    },
+    Delete {
+        path: PathBuf,
+    },
 }
 
-impl<'lua> IntoLua for Action {
+#[derive(Debug, Clone, PartialEq)]
+pub struct Package {
+    pub name: String,
+    /// Name of the package manager that installs the package, e.g. `apt`.
+    pub manager: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Service {
+    /// Full unit name, e.g. `nginx.service`.
+    pub name: String,
+    /// Content of the unit file, or `None` for units managed elsewhere.
+    pub unit: Option<Vec<u8>>,
+    pub enabled: Option<bool>,
+    pub running: Option<bool>,
+    pub restart_on: Vec<PathBuf>,
+}
+
+/// Commands to run when any of the paths, or anything below them, changes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hook {
+    pub paths: Vec<PathBuf>,
+    pub commands: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    pub target: PathBuf,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Directory {
+    Create {
+        path: PathBuf,
+        permissions: Permissions,
+    },
+    Delete {
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Permissions {
+    pub mode: Option<u32>,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+}
+
+impl IntoLua for Permissions {
+    fn into_lua(self, lua: &Lua) -> mlua::prelude::LuaResult<Value> {
+        let table = lua.create_table()?;
+
+        table.set("mode", self.mode.map(|mode| format!("{:04o}", mode)))?;
+        table.set("owner", self.owner)?;
+        table.set("group", self.group)?;
+
+        Ok(Value::Table(table))
+    }
+}
+
+impl FromLua for Permissions {
+    fn from_lua(value: Value, _: &Lua) -> mlua::prelude::LuaResult<Self> {
+        let table = match value {
+            Value::Nil => return Ok(Self::default()),
+            Value::Table(table) => table,
+            _ => {
+                return Err(mlua::Error::FromLuaConversionError {
+                    from: value.type_name(),
+                    to: String::from("Permissions"),
+                    message: Some(String::from("Expected a table of file options")),
+                })
+            }
+        };
+
+        let mode = match table.get::<Option<String>>("mode")? {
+            Some(mode) => Some(u32::from_str_radix(&mode, 8).map_err(|_| {
+                mlua::Error::FromLuaConversionError {
+                    from: "string",
+                    to: String::from("Permissions"),
+                    message: Some(format!("Invalid octal file mode: {}", mode)),
+                }
+            })?),
+            None => None,
+        };
+
+        Ok(Self {
+            mode,
+            owner: table.get("owner")?,
+            group: table.get("group")?,
+        })
+    }
+}
+
+impl IntoLua for Action {
     fn into_lua(self, lua: &Lua) -> mlua::prelude::LuaResult<Value> {
         let table = lua.create_table()?;
 
@@ -35,15 +146,20 @@ impl<'lua> IntoLua for Action {
             Action::File(file) => {
                 table.set("action", "file")?;
                 match file {
-                    File::Set { path, content } => {
+                    File::Set {
+                        path,
+                        content,
+                        permissions,
+                    } => {
                         table.set("method", "set")?;
                         table.set("path", path)?;
-                        table.set("content", content)?;
+                        table.set("content", lua.create_string(content)?)?;
+                        table.set("permissions", permissions)?;
                     }
                     File::Append { path, content } => {
                         table.set("method", "append")?;
                         table.set("path", path)?;
-                        table.set("content", content)?;
+                        table.set("content", lua.create_string(content)?)?;
                     }
                     File::Delete { path } => {
                         table.set("method", "delete")?;
@@ -51,8 +167,52 @@ impl<'lua> IntoLua for Action {
                     }
                 }
             }
+            Action::Directory(directory) => {
+                table.set("action", "directory")?;
+                match directory {
+                    Directory::Create { path, permissions } => {
+                        table.set("method", "create")?;
+                        table.set("path", path)?;
+                        table.set("permissions", permissions)?;
+                    }
+                    Directory::Delete { path } => {
+                        table.set("method", "delete")?;
+                        table.set("path", path)?;
+                    }
+                }
+            }
+            Action::Link(link) => {
+                table.set("action", "link")?;
+                table.set("target", link.target)?;
+                table.set("path", link.path)?;
+            }
+            Action::Package(package) => {
+                table.set("action", "package")?;
+                table.set("name", package.name)?;
+                table.set("manager", package.manager)?;
+            }
+            Action::Service(service) => {
+                table.set("action", "service")?;
+                table.set("name", service.name)?;
+                table.set(
+                    "unit",
+                    service
+                        .unit
+                        .map(|unit| lua.create_string(unit))
+                        .transpose()?,
+                )?;
+                table.set("enabled", service.enabled)?;
+                table.set("running", service.running)?;
+                table.set("restart_on", service.restart_on)?;
+            }
+            Action::Hook(hook) => {
+                table.set("action", "hook")?;
+                table.set("paths", hook.paths)?;
+                table.set("commands", hook.commands)?;
+            }
             Action::Script(script) => {
                 table.set("action", "script")?;
+                table.set("name", script.name)?;
                 table.set("install", script.install)?;
                 table.set("update", script.update)?;
                 table.set("uninstall", script.uninstall)?;
@@ -63,7 +223,7 @@ impl<'lua> IntoLua for Action {
     }
 }
 
-impl<'lua> FromLua for Action {
+impl FromLua for Action {
     fn from_lua(value: Value, _: &Lua) -> mlua::prelude::LuaResult<Self> {
         let table = value.as_table().unwrap();
 
@@ -74,15 +234,18 @@ impl<'lua> FromLua for Action {
                 match method.as_str() {
                     "set" => Ok(Self::File(File::Set {
                         path: table.get("path")?,
-                        content: table.get("content")?,
+                        content: table.get::<mlua::String>("content")?.as_bytes().to_vec(),
+                        permissions: table.get("permissions")?,
                     })),
                     "append" => Ok(Self::File(File::Set {
                         path: table.get("path")?,
-                        content: table.get("content")?,
+                        content: table.get::<mlua::String>("content")?.as_bytes().to_vec(),
+                        permissions: Permissions::default(),
                     })),
                     "delete" => Ok(Self::File(File::Set {
                         path: table.get("path")?,
-                        content: table.get("content")?,
+                        content: table.get::<mlua::String>("content")?.as_bytes().to_vec(),
+                        permissions: Permissions::default(),
                     })),
                     &_ => Err(mlua::Error::FromLuaConversionError {
                         from: "action",
@@ -91,7 +254,46 @@ impl<'lua> FromLua for Action {
                     }),
                 }
             }
+            "directory" => {
+                let method: String = table.get("method")?;
+                match method.as_str() {
+                    "create" => Ok(Self::Directory(Directory::Create {
+                        path: table.get("path")?,
+                        permissions: table.get("permissions")?,
+                    })),
+                    "delete" => Ok(Self::Directory(Directory::Delete {
+                        path: table.get("path")?,
+                    })),
+                    &_ => Err(mlua::Error::FromLuaConversionError {
+                        from: "action",
+                        to: String::from("Action"),
+                        message: Some(String::from("Invalid directory method")),
+                    }),
+                }
+            }
+            "link" => Ok(Self::Link(Link {
+                target: table.get("target")?,
+                path: table.get("path")?,
+            })),
+            "package" => Ok(Self::Package(Package {
+                name: table.get("name")?,
+                manager: table.get("manager")?,
+            })),
+            "service" => Ok(Self::Service(Service {
+                name: table.get("name")?,
+                unit: table
+                    .get::<Option<mlua::String>>("unit")?
+                    .map(|unit| unit.as_bytes().to_vec()),
+                enabled: table.get("enabled")?,
+                running: table.get("running")?,
+                restart_on: table.get("restart_on")?,
+            })),
+            "hook" => Ok(Self::Hook(Hook {
+                paths: table.get("paths")?,
+                commands: table.get("commands")?,
+            })),
             "script" => Ok(Self::Script(Script {
+                name: table.get("name")?,
                 install: table.get("install")?,
                 update: table.get("update")?,
                 uninstall: table.get("uninstall")?,
@@ -0,0 +1,372 @@
+//! A small template language for managed files.
+//!
+//! - `{{ name }}` and `{{ user.name }}` interpolate variables.
+//! - `{% if name %} ... {% else %} ... {% end %}` renders a branch depending on whether a
+//!   variable is set to something other than `nil` or `false`. `{% if not name %}` negates it.
+//! - `{% for item in items %} ... {% end %}` repeats its body for every item of a list.
+//! - `\{{` and `\{%` render the delimiters literally.
+//!
+//! A tag alone on its line is removed together with that line.
+
+use std::collections::BTreeMap;
+
+use mlua::{FromLua, Lua};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(String),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+impl Value {
+    fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Boolean(false))
+    }
+}
+
+impl FromLua for Value {
+    fn from_lua(value: mlua::Value, _: &Lua) -> mlua::Result<Self> {
+        match value {
+            mlua::Value::Nil => Ok(Value::Nil),
+            mlua::Value::Boolean(boolean) => Ok(Value::Boolean(boolean)),
+            mlua::Value::Integer(integer) => Ok(Value::Integer(integer)),
+            mlua::Value::Number(number) => Ok(Value::Number(number)),
+            mlua::Value::String(string) => Ok(Value::String(string.to_str()?.to_string())),
+            mlua::Value::Table(table) => {
+                let length = table.raw_len();
+
+                if length > 0 && table.clone().pairs::<mlua::Value, mlua::Value>().count() == length
+                {
+                    return table
+                        .sequence_values::<Value>()
+                        .collect::<mlua::Result<_>>()
+                        .map(Value::List);
+                }
+
+                table
+                    .pairs::<String, Value>()
+                    .collect::<mlua::Result<_>>()
+                    .map(Value::Map)
+            }
+            _ => Err(mlua::Error::FromLuaConversionError {
+                from: value.type_name(),
+                to: String::from("Value"),
+                message: Some(String::from("Template variables must be plain data")),
+            }),
+        }
+    }
+}
+
+/// A message along with the template line it refers to.
+type Error = (usize, String);
+
+/// The source and line of the `else` or `end` tag that closed a block.
+type Tag = (String, usize);
+
+#[derive(Debug)]
+enum Token {
+    Text(String),
+    Expression { source: String, line: usize },
+    Tag { source: String, line: usize },
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Variable {
+        path: String,
+        line: usize,
+    },
+    If {
+        path: String,
+        negated: bool,
+        then_nodes: Vec<Node>,
+        else_nodes: Vec<Node>,
+    },
+    For {
+        name: String,
+        path: String,
+        line: usize,
+        body: Vec<Node>,
+    },
+}
+
+/// Renders `template`, reporting errors as `name:line: message`.
+pub fn render(name: &str, template: &str, variables: &Value) -> Result<String, String> {
+    let error = |line: usize, message: String| format!("{}:{}: {}", name, line, message);
+
+    let tokens = tokenize(template).map_err(|(line, message)| error(line, message))?;
+    let mut tokens = tokens.into_iter();
+    let (nodes, end) = parse(&mut tokens).map_err(|(line, message)| error(line, message))?;
+
+    if let Some((tag, line)) = end {
+        return Err(error(line, format!("Unexpected {{% {} %}}", tag)));
+    }
+
+    let mut scopes = vec![variables.clone()];
+    let mut output = String::new();
+    render_nodes(&nodes, &mut scopes, &mut output)
+        .map_err(|(line, message)| error(line, message))?;
+
+    Ok(output)
+}
+
+fn tokenize(template: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut text = String::new();
+    let mut line = 1;
+    let mut rest = template;
+
+    while !rest.is_empty() {
+        if rest.starts_with("\\{{") || rest.starts_with("\\{%") {
+            text.push_str(&rest[1..3]);
+            rest = &rest[3..];
+            continue;
+        }
+
+        let closing = if rest.starts_with("{{") {
+            "}}"
+        } else if rest.starts_with("{%") {
+            "%}"
+        } else {
+            let character = rest.chars().next().unwrap();
+            if character == '\n' {
+                line += 1;
+            }
+            text.push(character);
+            rest = &rest[character.len_utf8()..];
+            continue;
+        };
+
+        let end = rest
+            .find(closing)
+            .ok_or_else(|| (line, format!("Unclosed {}", &rest[..2])))?;
+        let source = rest[2..end].trim().to_string();
+
+        tokens.push(Token::Text(std::mem::take(&mut text)));
+        tokens.push(if closing == "}}" {
+            Token::Expression { source, line }
+        } else {
+            Token::Tag { source, line }
+        });
+
+        line += rest[..end].matches('\n').count();
+        rest = &rest[end + 2..];
+    }
+
+    tokens.push(Token::Text(text));
+
+    trim_standalone_tags(&mut tokens);
+
+    Ok(tokens)
+}
+
+/// Removes the indentation and line break around tags that sit alone on their line.
+fn trim_standalone_tags(tokens: &mut [Token]) {
+    let mut truncations = Vec::new();
+    let mut drains = Vec::new();
+
+    // Tokens alternate between text and expressions or tags, starting and ending with text.
+    for index in (1..tokens.len()).step_by(2) {
+        let (Token::Text(before), Token::Tag { .. }, Token::Text(after)) =
+            (&tokens[index - 1], &tokens[index], &tokens[index + 1])
+        else {
+            continue;
+        };
+
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+        let starts_line = before.contains('\n') || index == 1;
+        if !starts_line || !before[line_start..].trim().is_empty() {
+            continue;
+        }
+
+        let line_end = after.find('\n').map_or(after.len(), |index| index + 1);
+        let ends_line = after.contains('\n') || index + 2 == tokens.len();
+        if !ends_line || !after[..line_end].trim().is_empty() {
+            continue;
+        }
+
+        truncations.push((index - 1, line_start));
+        drains.push((index + 1, line_end));
+    }
+
+    // A text between two standalone tags is cut at both ends, so both cuts are decided on the
+    // original text first. The cut for the later tag never starts before the earlier one ends.
+    for (index, line_start) in truncations {
+        if let Token::Text(text) = &mut tokens[index] {
+            text.truncate(line_start);
+        }
+    }
+
+    for (index, line_end) in drains {
+        if let Token::Text(text) = &mut tokens[index] {
+            text.drain(..line_end.min(text.len()));
+        }
+    }
+}
+
+/// Parses nodes until the end of the tokens or an `else` or `end` tag, which is returned.
+fn parse(tokens: &mut std::vec::IntoIter<Token>) -> Result<(Vec<Node>, Option<Tag>), Error> {
+    let mut nodes = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Text(text) => {
+                if !text.is_empty() {
+                    nodes.push(Node::Text(text));
+                }
+            }
+            Token::Expression { source, line } => {
+                if !is_path(&source) {
+                    return Err((line, format!("Invalid variable: {}", source)));
+                }
+
+                nodes.push(Node::Variable { path: source, line });
+            }
+            Token::Tag { source, line } => {
+                let words = source.split_whitespace().collect::<Vec<_>>();
+
+                match words.as_slice() {
+                    ["else"] | ["end"] => return Ok((nodes, Some((source, line)))),
+                    ["if", path] | ["if", "not", path] if is_path(path) => {
+                        let negated = words.len() == 3;
+                        let (then_nodes, end) = parse(tokens)?;
+
+                        let else_nodes = match end {
+                            Some((tag, _)) if tag == "end" => Vec::new(),
+                            Some((tag, else_line)) if tag == "else" => match parse(tokens)? {
+                                (else_nodes, Some((tag, _))) if tag == "end" => else_nodes,
+                                (_, Some((tag, line))) => {
+                                    return Err((line, format!("Unexpected {{% {} %}}", tag)))
+                                }
+                                (_, None) => {
+                                    return Err((else_line, String::from("Unclosed {% if %}")))
+                                }
+                            },
+                            _ => return Err((line, String::from("Unclosed {% if %}"))),
+                        };
+
+                        nodes.push(Node::If {
+                            path: path.to_string(),
+                            negated,
+                            then_nodes,
+                            else_nodes,
+                        });
+                    }
+                    ["for", name, "in", path] if is_path(name) && is_path(path) => {
+                        let body = match parse(tokens)? {
+                            (body, Some((tag, _))) if tag == "end" => body,
+                            (_, Some((tag, line))) => {
+                                return Err((line, format!("Unexpected {{% {} %}}", tag)))
+                            }
+                            (_, None) => return Err((line, String::from("Unclosed {% for %}"))),
+                        };
+
+                        nodes.push(Node::For {
+                            name: name.to_string(),
+                            path: path.to_string(),
+                            line,
+                            body,
+                        });
+                    }
+                    _ => return Err((line, format!("Invalid tag: {{% {} %}}", source))),
+                }
+            }
+        }
+    }
+
+    Ok((nodes, None))
+}
+
+fn is_path(source: &str) -> bool {
+    !source.is_empty()
+        && source.split('.').all(|part| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|character| character.is_ascii_alphanumeric() || character == '_')
+        })
+}
+
+fn render_nodes(nodes: &[Node], scopes: &mut Vec<Value>, output: &mut String) -> Result<(), Error> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Variable { path, line } => match lookup(scopes, path) {
+                Value::Nil => return Err((*line, format!("Undefined variable: {}", path))),
+                Value::Boolean(boolean) => output.push_str(&boolean.to_string()),
+                Value::Integer(integer) => output.push_str(&integer.to_string()),
+                Value::Number(number) => output.push_str(&number.to_string()),
+                Value::String(string) => output.push_str(&string),
+                Value::List(_) | Value::Map(_) => {
+                    return Err((*line, format!("Cannot render table: {}", path)))
+                }
+            },
+            Node::If {
+                path,
+                negated,
+                then_nodes,
+                else_nodes,
+            } => {
+                if lookup(scopes, path).is_truthy() != *negated {
+                    render_nodes(then_nodes, scopes, output)?;
+                } else {
+                    render_nodes(else_nodes, scopes, output)?;
+                }
+            }
+            Node::For {
+                name,
+                path,
+                line,
+                body,
+            } => {
+                let items = match lookup(scopes, path) {
+                    Value::List(items) => items,
+                    // Lua cannot tell an empty list from an empty table.
+                    Value::Map(map) if map.is_empty() => Vec::new(),
+                    Value::Nil => Vec::new(),
+                    _ => return Err((*line, format!("Cannot loop over non-list: {}", path))),
+                };
+
+                for item in items {
+                    scopes.push(Value::Map(BTreeMap::from([(name.clone(), item)])));
+                    let result = render_nodes(body, scopes, output);
+                    scopes.pop();
+                    result?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolves a dotted path against the innermost scope defining its first part.
+fn lookup(scopes: &[Value], path: &str) -> Value {
+    let mut parts = path.split('.');
+    let first = parts.next().unwrap_or_default();
+
+    let Some(mut value) = scopes.iter().rev().find_map(|scope| match scope {
+        Value::Map(map) => map.get(first),
+        _ => None,
+    }) else {
+        return Value::Nil;
+    };
+
+    for part in parts {
+        value = match value {
+            Value::Map(map) => match map.get(part) {
+                Some(value) => value,
+                None => return Value::Nil,
+            },
+            _ => return Value::Nil,
+        };
+    }
+
+    value.clone()
+}
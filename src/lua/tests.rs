@@ -1,10 +1,13 @@
-use std::path::PathBuf;
+use std::{collections::BTreeMap, path::PathBuf};
 
 use assert_fs::prelude::*;
 
-use crate::lua::models::{Action, Config, File, Script};
+use crate::lua::models::{
+    Action, Config, Directory, File, Hook, Link, Package, Permissions, Script, Service,
+};
 
-use super::parse_config;
+use super::{parse_config, template};
+use crate::facts::models::{Distro, Facts, User};
 
 #[test]
 fn parse_config_script() {
@@ -12,12 +15,19 @@ fn parse_config_script() {
     let init_lua_file = config_directory.child("init.lua");
     init_lua_file.write_str("carbide.script({ \"sudo apt-get install neovim\"}, {}, { \"sudo apt-get uninstall neovim\" })").unwrap();
 
-    let config = parse_config(&PathBuf::from(config_directory.path())).unwrap();
+    let config = parse_config(
+        &PathBuf::from(config_directory.path()),
+        None,
+        &Facts::default(),
+    )
+    .unwrap();
 
     assert_eq!(
         config,
         Config {
+            profile: None,
             actions: vec![Action::Script(Script {
+                name: None,
                 install: vec![String::from("sudo apt-get install neovim")],
                 update: vec![],
                 uninstall: vec![String::from("sudo apt-get uninstall neovim")],
@@ -26,6 +36,33 @@ fn parse_config_script() {
     )
 }
 
+#[test]
+fn parse_config_named_script() {
+    let config_directory = assert_fs::TempDir::new().unwrap();
+    let init_lua_file = config_directory.child("init.lua");
+    init_lua_file.write_str("carbide.script({ \"sudo apt-get install neovim\"}, { \"sudo apt-get upgrade neovim\" }, { \"sudo apt-get uninstall neovim\" }, \"neovim\")").unwrap();
+
+    let config = parse_config(
+        &PathBuf::from(config_directory.path()),
+        None,
+        &Facts::default(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        config,
+        Config {
+            profile: None,
+            actions: vec![Action::Script(Script {
+                name: Some(String::from("neovim")),
+                install: vec![String::from("sudo apt-get install neovim")],
+                update: vec![String::from("sudo apt-get upgrade neovim")],
+                uninstall: vec![String::from("sudo apt-get uninstall neovim")],
+            })]
+        }
+    )
+}
+
 #[test]
 fn parse_config_file_set() {
     let config_directory = assert_fs::TempDir::new().unwrap();
@@ -34,19 +71,204 @@ fn parse_config_file_set() {
         .write_str("carbide.file.set(\"/etc/neovim/init.lua\", \"print(\\\"Hello World\\\")\")")
         .unwrap();
 
-    let config = parse_config(&PathBuf::from(config_directory.path())).unwrap();
+    let config = parse_config(
+        &PathBuf::from(config_directory.path()),
+        None,
+        &Facts::default(),
+    )
+    .unwrap();
 
     assert_eq!(
         config,
         Config {
+            profile: None,
             actions: vec![Action::File(File::Set {
                 path: PathBuf::from("/etc/neovim/init.lua"),
-                content: String::from("print(\"Hello World\")")
+                content: b"print(\"Hello World\")".to_vec(),
+                permissions: Permissions::default(),
+            })]
+        }
+    )
+}
+
+#[test]
+fn parse_config_file_set_permissions() {
+    let config_directory = assert_fs::TempDir::new().unwrap();
+    let init_lua_file = config_directory.child("init.lua");
+    init_lua_file
+        .write_str("carbide.file.set(\"/etc/ssh/sshd_config\", \"PermitRootLogin no\", { mode = \"0600\", owner = \"root\", group = \"ssh\" })")
+        .unwrap();
+
+    let config = parse_config(
+        &PathBuf::from(config_directory.path()),
+        None,
+        &Facts::default(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        config,
+        Config {
+            profile: None,
+            actions: vec![Action::File(File::Set {
+                path: PathBuf::from("/etc/ssh/sshd_config"),
+                content: b"PermitRootLogin no".to_vec(),
+                permissions: Permissions {
+                    mode: Some(0o600),
+                    owner: Some(String::from("root")),
+                    group: Some(String::from("ssh")),
+                },
+            })]
+        }
+    )
+}
+
+#[test]
+fn parse_config_file_set_binary() {
+    let config_directory = assert_fs::TempDir::new().unwrap();
+    let init_lua_file = config_directory.child("init.lua");
+    init_lua_file
+        .write_str("carbide.file.set(\"/usr/share/keyrings/carbide.gpg\", \"\\x99\\x00\\xff\")")
+        .unwrap();
+
+    let config = parse_config(
+        &PathBuf::from(config_directory.path()),
+        None,
+        &Facts::default(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        config,
+        Config {
+            profile: None,
+            actions: vec![Action::File(File::Set {
+                path: PathBuf::from("/usr/share/keyrings/carbide.gpg"),
+                content: vec![0x99, 0x00, 0xff],
+                permissions: Permissions::default(),
+            })]
+        }
+    )
+}
+
+#[test]
+fn parse_config_file_source() {
+    let config_directory = assert_fs::TempDir::new().unwrap();
+    config_directory
+        .child("files/nginx.conf")
+        .write_str("worker_processes 4;")
+        .unwrap();
+    let init_lua_file = config_directory.child("init.lua");
+    init_lua_file
+        .write_str("carbide.file.source(\"/etc/nginx/nginx.conf\", \"files/nginx.conf\", { mode = \"0644\" })")
+        .unwrap();
+
+    let config = parse_config(
+        &PathBuf::from(config_directory.path()),
+        None,
+        &Facts::default(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        config,
+        Config {
+            profile: None,
+            actions: vec![Action::File(File::Set {
+                path: PathBuf::from("/etc/nginx/nginx.conf"),
+                content: b"worker_processes 4;".to_vec(),
+                permissions: Permissions {
+                    mode: Some(0o644),
+                    owner: None,
+                    group: None,
+                },
             })]
         }
     )
 }
 
+#[test]
+fn parse_config_file_source_missing() {
+    let config_directory = assert_fs::TempDir::new().unwrap();
+    let init_lua_file = config_directory.child("init.lua");
+    init_lua_file
+        .write_str("carbide.file.source(\"/etc/nginx/nginx.conf\", \"files/nginx.conf\")")
+        .unwrap();
+
+    let err = parse_config(
+        &PathBuf::from(config_directory.path()),
+        None,
+        &Facts::default(),
+    )
+    .unwrap_err();
+
+    assert!(err.to_string().contains("files/nginx.conf"));
+}
+
+#[test]
+fn parse_config_file_tree() {
+    let config_directory = assert_fs::TempDir::new().unwrap();
+    config_directory
+        .child("files/nvim/init.lua")
+        .write_str("require(\"plugins\")")
+        .unwrap();
+    config_directory
+        .child("files/nvim/lua/plugins.lua")
+        .write_str("return {}")
+        .unwrap();
+    std::os::unix::fs::symlink(
+        config_directory.child("files/nvim").path(),
+        config_directory.child("files/nvim/lua/loop").path(),
+    )
+    .unwrap();
+    let init_lua_file = config_directory.child("init.lua");
+    init_lua_file
+        .write_str("carbide.file.tree(\"/home/carbide/.config/nvim\", \"files/nvim\")")
+        .unwrap();
+
+    let config = parse_config(
+        &PathBuf::from(config_directory.path()),
+        None,
+        &Facts::default(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        config,
+        Config {
+            profile: None,
+            actions: vec![
+                Action::File(File::Set {
+                    path: PathBuf::from("/home/carbide/.config/nvim/init.lua"),
+                    content: b"require(\"plugins\")".to_vec(),
+                    permissions: Permissions::default(),
+                }),
+                Action::File(File::Set {
+                    path: PathBuf::from("/home/carbide/.config/nvim/lua/plugins.lua"),
+                    content: b"return {}".to_vec(),
+                    permissions: Permissions::default(),
+                }),
+            ]
+        }
+    )
+}
+
+#[test]
+fn parse_config_file_set_invalid_mode() {
+    let config_directory = assert_fs::TempDir::new().unwrap();
+    let init_lua_file = config_directory.child("init.lua");
+    init_lua_file
+        .write_str("carbide.file.set(\"/etc/ssh/sshd_config\", \"\", { mode = \"0900\" })")
+        .unwrap();
+
+    assert!(parse_config(
+        &PathBuf::from(config_directory.path()),
+        None,
+        &Facts::default()
+    )
+    .is_err());
+}
+
 #[test]
 fn parse_config_file_append() {
     let config_directory = assert_fs::TempDir::new().unwrap();
@@ -58,19 +280,25 @@ carbide.file.append(\"/etc/neovim/init.lua\", \"print(\\\"Hello World 2\\\")\")"
         )
         .unwrap();
 
-    let config = parse_config(&PathBuf::from(config_directory.path())).unwrap();
+    let config = parse_config(
+        &PathBuf::from(config_directory.path()),
+        None,
+        &Facts::default(),
+    )
+    .unwrap();
 
     assert_eq!(
         config,
         Config {
+            profile: None,
             actions: vec![
                 Action::File(File::Append {
                     path: PathBuf::from("/etc/neovim/init.lua"),
-                    content: String::from("print(\"Hello World\")")
+                    content: b"print(\"Hello World\")".to_vec()
                 }),
                 Action::File(File::Append {
                     path: PathBuf::from("/etc/neovim/init.lua"),
-                    content: String::from("print(\"Hello World 2\")")
+                    content: b"print(\"Hello World 2\")".to_vec()
                 })
             ]
         }
@@ -85,14 +313,611 @@ fn parse_config_file_delete() {
         .write_str("carbide.file.delete(\"/etc/neovim/init.lua\")")
         .unwrap();
 
-    let config = parse_config(&PathBuf::from(config_directory.path())).unwrap();
+    let config = parse_config(
+        &PathBuf::from(config_directory.path()),
+        None,
+        &Facts::default(),
+    )
+    .unwrap();
 
     assert_eq!(
         config,
         Config {
+            profile: None,
             actions: vec![Action::File(File::Delete {
                 path: PathBuf::from("/etc/neovim/init.lua"),
             })]
         }
     )
 }
+
+#[test]
+fn parse_config_directory() {
+    let config_directory = assert_fs::TempDir::new().unwrap();
+    let init_lua_file = config_directory.child("init.lua");
+    init_lua_file
+        .write_str(
+            "carbide.directory.create(\"/etc/neovim\", { mode = \"0755\" })
+carbide.directory.delete(\"/etc/vim\")",
+        )
+        .unwrap();
+
+    let config = parse_config(
+        &PathBuf::from(config_directory.path()),
+        None,
+        &Facts::default(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        config,
+        Config {
+            profile: None,
+            actions: vec![
+                Action::Directory(Directory::Create {
+                    path: PathBuf::from("/etc/neovim"),
+                    permissions: Permissions {
+                        mode: Some(0o755),
+                        owner: None,
+                        group: None,
+                    },
+                }),
+                Action::Directory(Directory::Delete {
+                    path: PathBuf::from("/etc/vim"),
+                })
+            ]
+        }
+    )
+}
+
+#[test]
+fn parse_config_link() {
+    let config_directory = assert_fs::TempDir::new().unwrap();
+    let init_lua_file = config_directory.child("init.lua");
+    init_lua_file
+        .write_str("carbide.link(\"/home/carbide/dotfiles/nvim\", \"/home/carbide/.config/nvim\")")
+        .unwrap();
+
+    let config = parse_config(
+        &PathBuf::from(config_directory.path()),
+        None,
+        &Facts::default(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        config,
+        Config {
+            profile: None,
+            actions: vec![Action::Link(Link {
+                target: PathBuf::from("/home/carbide/dotfiles/nvim"),
+                path: PathBuf::from("/home/carbide/.config/nvim"),
+            })]
+        }
+    )
+}
+
+#[test]
+fn parse_config_file_template() {
+    let config_directory = assert_fs::TempDir::new().unwrap();
+    config_directory
+        .child("templates/hosts")
+        .write_str(
+            "127.0.0.1 localhost
+127.0.1.1 {{ host.name }}
+{% for alias in aliases %}
+{% if alias.enabled %}
+{{ alias.address }} {{ alias.name }}
+{% end %}
+{% end %}
+",
+        )
+        .unwrap();
+    let init_lua_file = config_directory.child("init.lua");
+    init_lua_file
+        .write_str(
+            "carbide.file.template(\"/etc/hosts\", { source = \"templates/hosts\" }, {
+                host = { name = \"carbide\" },
+                aliases = {
+                    { name = \"nas\", address = \"10.0.0.2\", enabled = true },
+                    { name = \"printer\", address = \"10.0.0.3\", enabled = false },
+                },
+            })",
+        )
+        .unwrap();
+
+    let config = parse_config(
+        &PathBuf::from(config_directory.path()),
+        None,
+        &Facts::default(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        config,
+        Config {
+            profile: None,
+            actions: vec![Action::File(File::Set {
+                path: PathBuf::from("/etc/hosts"),
+                content: b"127.0.0.1 localhost\n127.0.1.1 carbide\n10.0.0.2 nas\n".to_vec(),
+                permissions: Permissions::default(),
+            })]
+        }
+    )
+}
+
+#[test]
+fn parse_config_file_template_text() {
+    let config_directory = assert_fs::TempDir::new().unwrap();
+    let init_lua_file = config_directory.child("init.lua");
+    init_lua_file
+        .write_str(
+            "carbide.file.template(\"/etc/motd\", { text = \"Welcome to {{ name }}\" }, { name = \"carbide\" })",
+        )
+        .unwrap();
+
+    let config = parse_config(
+        &PathBuf::from(config_directory.path()),
+        None,
+        &Facts::default(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        config,
+        Config {
+            profile: None,
+            actions: vec![Action::File(File::Set {
+                path: PathBuf::from("/etc/motd"),
+                content: b"Welcome to carbide".to_vec(),
+                permissions: Permissions::default(),
+            })]
+        }
+    );
+
+    for script in [
+        "carbide.file.template(\"/etc/motd\", {}, {})",
+        "carbide.file.template(\"/etc/motd\", { source = \"motd\", text = \"motd\" }, {})",
+    ] {
+        init_lua_file.write_str(script).unwrap();
+
+        let err = parse_config(
+            &PathBuf::from(config_directory.path()),
+            None,
+            &Facts::default(),
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("needs either a source or a text"));
+    }
+}
+
+#[test]
+fn render_template() {
+    let variables = template::Value::Map(BTreeMap::from([
+        (
+            String::from("user"),
+            template::Value::String(String::from("carbide")),
+        ),
+        (String::from("port"), template::Value::Integer(22)),
+        (String::from("root"), template::Value::Boolean(false)),
+        // An empty Lua table, which may have been meant as a list.
+        (
+            String::from("groups"),
+            template::Value::Map(BTreeMap::new()),
+        ),
+    ]));
+
+    assert_eq!(
+        template::render(
+            "sshd_config",
+            "Port {{ port }}\nAllowUsers {{user}}\nPermitRootLogin {% if root %}yes{% else %}no{% end %}\n{% if not missing %}# \\{{ literal }}{% end %}\n{% for group in groups %}AllowGroups {{ group }}\n{% end %}",
+            &variables
+        )
+        .unwrap(),
+        "Port 22\nAllowUsers carbide\nPermitRootLogin no\n# {{ literal }}\n"
+    );
+}
+
+#[test]
+fn render_template_errors() {
+    let variables = template::Value::Map(BTreeMap::new());
+
+    assert_eq!(
+        template::render("motd", "Hello\n{{ user }}\n", &variables).unwrap_err(),
+        "motd:2: Undefined variable: user"
+    );
+    assert_eq!(
+        template::render("motd", "Hello\n\n{% if user %}\n", &variables).unwrap_err(),
+        "motd:3: Unclosed {% if %}"
+    );
+    assert_eq!(
+        template::render("motd", "{{ user", &variables).unwrap_err(),
+        "motd:1: Unclosed {{"
+    );
+    assert_eq!(
+        template::render("motd", "{% while user %}", &variables).unwrap_err(),
+        "motd:1: Invalid tag: {% while user %}"
+    );
+}
+
+#[test]
+fn parse_config_facts() {
+    let config_directory = assert_fs::TempDir::new().unwrap();
+    let init_lua_file = config_directory.child("init.lua");
+    init_lua_file
+        .write_str(
+            "if carbide.facts.distro.id == \"debian\" and carbide.facts.cpu_count == 8 then
+                carbide.file.set(\"/etc/motd\", carbide.facts.hostname .. \" \" .. carbide.facts.users.nandesh.home)
+            end
+            assert(not pcall(function() carbide.facts.hostname = \"other\" end))
+            assert(not pcall(function() carbide.facts.distro.id = \"arch\" end))
+            for name, user in pairs(carbide.facts.users) do
+                carbide.file.set(\"/etc/users/\" .. name, tostring(user.uid))
+            end",
+        )
+        .unwrap();
+
+    let facts = Facts {
+        hostname: String::from("carbide"),
+        distro: Distro {
+            id: String::from("debian"),
+            name: String::from("Debian GNU/Linux"),
+            version: String::from("12"),
+        },
+        cpu_count: 8,
+        users: vec![User {
+            name: String::from("nandesh"),
+            uid: 1000,
+            gid: 1000,
+            home: String::from("/home/nandesh"),
+            shell: String::from("/bin/zsh"),
+        }],
+        ..Facts::default()
+    };
+
+    let config = parse_config(&PathBuf::from(config_directory.path()), None, &facts).unwrap();
+
+    assert_eq!(
+        config,
+        Config {
+            profile: None,
+            actions: vec![
+                Action::File(File::Set {
+                    path: PathBuf::from("/etc/motd"),
+                    content: b"carbide /home/nandesh".to_vec(),
+                    permissions: Permissions::default(),
+                }),
+                Action::File(File::Set {
+                    path: PathBuf::from("/etc/users/nandesh"),
+                    content: b"1000".to_vec(),
+                    permissions: Permissions::default(),
+                }),
+            ]
+        }
+    )
+}
+
+#[test]
+fn parse_config_standard_library() {
+    let config_directory = assert_fs::TempDir::new().unwrap();
+    let init_lua_file = config_directory.child("init.lua");
+    init_lua_file
+        .write_str(
+            "assert(os == nil and io == nil and debug == nil)
+            local ports = { 80, 443 }
+            carbide.file.set(\"/etc/ports\", string.format(\"%s %d %s\", table.concat(ports, \",\"), math.max(table.unpack(ports)), utf8.char(955)))",
+        )
+        .unwrap();
+
+    let config = parse_config(
+        &PathBuf::from(config_directory.path()),
+        None,
+        &Facts::default(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        config,
+        Config {
+            profile: None,
+            actions: vec![Action::File(File::Set {
+                path: PathBuf::from("/etc/ports"),
+                content: "80,443 443 λ".as_bytes().to_vec(),
+                permissions: Permissions::default(),
+            })]
+        }
+    )
+}
+
+#[test]
+fn parse_config_fs() {
+    let config_directory = assert_fs::TempDir::new().unwrap();
+    config_directory
+        .child("keys/alice.pub")
+        .write_str("ssh-ed25519 alice")
+        .unwrap();
+    config_directory
+        .child("keys/bob.pub")
+        .write_str("ssh-ed25519 bob")
+        .unwrap();
+    config_directory.child("keys/README").write_str("").unwrap();
+    let init_lua_file = config_directory.child("init.lua");
+    init_lua_file
+        .write_str(
+            "local keys = {}
+            for _, path in ipairs(carbide.fs.glob(\"keys/*.pub\")) do
+                table.insert(keys, carbide.fs.read(path))
+            end
+            assert(carbide.fs.exists(\"keys/README\") and not carbide.fs.exists(\"keys/carol.pub\"))
+            carbide.file.set(\"/etc/keys\", table.concat(carbide.fs.list(\"keys\"), \" \") .. \"\\n\" .. table.concat(keys, \"\\n\"))",
+        )
+        .unwrap();
+
+    let config = parse_config(
+        &PathBuf::from(config_directory.path()),
+        None,
+        &Facts::default(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        config,
+        Config {
+            profile: None,
+            actions: vec![Action::File(File::Set {
+                path: PathBuf::from("/etc/keys"),
+                content: b"README alice.pub bob.pub\nssh-ed25519 alice\nssh-ed25519 bob".to_vec(),
+                permissions: Permissions::default(),
+            })]
+        }
+    )
+}
+
+#[test]
+fn parse_config_fs_outside_config_directory() {
+    let root = assert_fs::TempDir::new().unwrap();
+    root.child("secret").write_str("Hello World").unwrap();
+    let config_directory = root.child("config");
+    config_directory.create_dir_all().unwrap();
+    std::os::unix::fs::symlink(
+        root.child("secret").path(),
+        config_directory.child("link").path(),
+    )
+    .unwrap();
+    config_directory.child("files").create_dir_all().unwrap();
+    std::os::unix::fs::symlink(
+        root.child("secret").path(),
+        config_directory.child("files/leak").path(),
+    )
+    .unwrap();
+
+    for script in [
+        "carbide.fs.read(\"../secret\")",
+        "carbide.fs.read(\"/etc/passwd\")",
+        "carbide.fs.read(\"link\")",
+        "carbide.fs.glob(\"../*\")",
+        "carbide.file.source(\"/etc/secret\", \"../secret\")",
+        "carbide.file.source(\"/etc/secret\", \"/etc/passwd\")",
+        "carbide.file.tree(\"/etc/secret\", \"..\")",
+        "carbide.file.tree(\"/etc/secret\", \"files\")",
+        "carbide.file.template(\"/etc/secret\", { source = \"../secret\" }, {})",
+    ] {
+        config_directory
+            .child("init.lua")
+            .write_str(script)
+            .unwrap();
+
+        let err = parse_config(
+            &PathBuf::from(config_directory.path()),
+            None,
+            &Facts::default(),
+        )
+        .unwrap_err();
+
+        assert!(err
+            .to_string()
+            .contains("Path is outside the config directory"));
+    }
+}
+
+#[test]
+fn parse_config_profile() {
+    let config_directory = assert_fs::TempDir::new().unwrap();
+    config_directory
+        .child("init.lua")
+        .write_str("carbide.file.set(\"/etc/role\", \"default \" .. tostring(carbide.profile))")
+        .unwrap();
+    config_directory
+        .child("hosts/server.lua")
+        .write_str("carbide.file.set(\"/etc/role\", \"server \" .. carbide.profile)")
+        .unwrap();
+
+    for (profile, recorded_profile, content) in [
+        (None, None, "default nil"),
+        (Some("server"), Some("server"), "server server"),
+        (Some("workstation"), None, "default workstation"),
+    ] {
+        let config = parse_config(
+            &PathBuf::from(config_directory.path()),
+            profile,
+            &Facts::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                profile: recorded_profile.map(String::from),
+                actions: vec![Action::File(File::Set {
+                    path: PathBuf::from("/etc/role"),
+                    content: content.as_bytes().to_vec(),
+                    permissions: Permissions::default(),
+                })]
+            }
+        );
+    }
+
+    assert!(parse_config(
+        &PathBuf::from(config_directory.path()),
+        Some("../init"),
+        &Facts::default()
+    )
+    .is_err());
+}
+
+#[test]
+fn parse_config_package() {
+    let config_directory = assert_fs::TempDir::new().unwrap();
+    config_directory
+        .child("init.lua")
+        .write_str(
+            "carbide.package(\"neovim\")
+            carbide.package(\"ripgrep\", { manager = \"pacman\" })",
+        )
+        .unwrap();
+
+    let facts = Facts {
+        distro: Distro {
+            id: String::from("debian"),
+            ..Distro::default()
+        },
+        ..Facts::default()
+    };
+
+    assert_eq!(
+        parse_config(&PathBuf::from(config_directory.path()), None, &facts).unwrap(),
+        Config {
+            profile: None,
+            actions: vec![
+                Action::Package(Package {
+                    name: String::from("neovim"),
+                    manager: String::from("apt"),
+                }),
+                Action::Package(Package {
+                    name: String::from("ripgrep"),
+                    manager: String::from("pacman"),
+                }),
+            ]
+        }
+    );
+
+    // Without a known distro the package manager has to be given explicitly.
+    assert!(parse_config(
+        &PathBuf::from(config_directory.path()),
+        None,
+        &Facts::default()
+    )
+    .is_err());
+
+    config_directory
+        .child("init.lua")
+        .write_str("carbide.package(\"neovim; reboot\", { manager = \"apt\" })")
+        .unwrap();
+
+    assert!(parse_config(&PathBuf::from(config_directory.path()), None, &facts).is_err());
+}
+
+#[test]
+fn parse_config_service() {
+    let config_directory = assert_fs::TempDir::new().unwrap();
+    config_directory
+        .child("init.lua")
+        .write_str(
+            "carbide.service({
+                name = \"nginx\",
+                unit = \"[Service]\\nExecStart=/usr/sbin/nginx\",
+                enabled = true,
+                state = \"running\",
+                restart_on = { \"/etc/nginx/nginx.conf\" },
+            })
+            carbide.service({ name = \"backup.timer\", state = \"stopped\" })",
+        )
+        .unwrap();
+
+    assert_eq!(
+        parse_config(
+            &PathBuf::from(config_directory.path()),
+            None,
+            &Facts::default()
+        )
+        .unwrap(),
+        Config {
+            profile: None,
+            actions: vec![
+                Action::Service(Service {
+                    name: String::from("nginx.service"),
+                    unit: Some(b"[Service]\nExecStart=/usr/sbin/nginx".to_vec()),
+                    enabled: Some(true),
+                    running: Some(true),
+                    restart_on: vec![PathBuf::from("/etc/nginx/nginx.conf")],
+                }),
+                Action::Service(Service {
+                    name: String::from("backup.timer"),
+                    unit: None,
+                    enabled: None,
+                    running: Some(false),
+                    restart_on: vec![],
+                }),
+            ]
+        }
+    );
+
+    config_directory
+        .child("init.lua")
+        .write_str("carbide.service({ name = \"nginx\", state = \"paused\" })")
+        .unwrap();
+
+    assert!(parse_config(
+        &PathBuf::from(config_directory.path()),
+        None,
+        &Facts::default()
+    )
+    .is_err());
+}
+
+#[test]
+fn parse_config_on_change() {
+    let config_directory = assert_fs::TempDir::new().unwrap();
+    config_directory
+        .child("init.lua")
+        .write_str(
+            "carbide.file.set(\"/etc/nginx/nginx.conf\", \"worker_processes 4;\", {
+                mode = \"644\",
+                on_change = { \"systemctl reload nginx\" },
+            })
+            carbide.on_change(\"/etc/motd\", { \"echo motd\", \"echo done\" })",
+        )
+        .unwrap();
+
+    assert_eq!(
+        parse_config(
+            &PathBuf::from(config_directory.path()),
+            None,
+            &Facts::default()
+        )
+        .unwrap(),
+        Config {
+            profile: None,
+            actions: vec![
+                Action::File(File::Set {
+                    path: PathBuf::from("/etc/nginx/nginx.conf"),
+                    content: b"worker_processes 4;".to_vec(),
+                    permissions: Permissions {
+                        mode: Some(0o644),
+                        owner: None,
+                        group: None,
+                    },
+                }),
+                Action::Hook(Hook {
+                    paths: vec![PathBuf::from("/etc/nginx/nginx.conf")],
+                    commands: vec![String::from("systemctl reload nginx")],
+                }),
+                Action::Hook(Hook {
+                    paths: vec![PathBuf::from("/etc/motd")],
+                    commands: vec![String::from("echo motd"), String::from("echo done")],
+                }),
+            ]
+        }
+    );
+}
@@ -1,24 +1,38 @@
+mod apply;
+mod backups;
 mod cli;
 mod difference;
+mod drift;
+mod execution;
+mod facts;
+mod filesystem;
 mod generations;
 mod lua;
+mod packages;
+mod services;
+mod store;
+#[cfg(test)]
+mod tests;
 
 use std::{
     error::Error,
-    fs::{remove_file, File, OpenOptions},
-    io::{self, Write},
-    path::PathBuf,
+    fs, io,
+    path::{Path, PathBuf},
 };
 
 use chrono::Local;
+use clap::ArgMatches;
 
 const DEFAULT_CONFIG_DIRECTORY: &str = "/etc/carbide";
 const DEFAULT_DATA_DIRECTORY: &str = "/var/lib/carbide";
+const DEFAULT_SHELL: &str = "/bin/sh";
 
 fn main() -> Result<(), Box<dyn Error>> {
     let matches = cli::get_matches();
     match matches.subcommand() {
-        Some(("switch", subcommand)) => {
+        Some((name @ ("switch" | "plan"), subcommand)) => {
+            let dry_run = name == "plan" || subcommand.get_flag("dry-run");
+
             let data_directory = PathBuf::from(
                 subcommand
                     .get_one::<String>("data-directory")
@@ -41,11 +55,32 @@ fn main() -> Result<(), Box<dyn Error>> {
                 config_directory.display()
             );
 
+            let profile = subcommand.get_one::<String>("profile");
+            if let Some(profile) = profile {
+                println!("[ Stage 1 ] ( Profile ) {}", profile);
+            }
+
             println!("[ Stage 1 ] ( Loading Config )");
-            let config = lua::parse_config(&config_directory)?;
+            let config = lua::parse_config(
+                &config_directory,
+                profile.map(String::as_str),
+                &facts::SystemCollector,
+            )?;
+
+            if let (Some(profile), None) = (profile, &config.profile) {
+                println!(
+                    "[ Stage 1 ] ( Warning ) No hosts/{}.lua, using init.lua",
+                    profile
+                );
+            }
+
+            let mut blobs = store::models::Blobs::default();
 
-            println!("[ Stage 2 ] ( Reading Last Generation )");
-            let previous_generation = match generations::read_last_generation(&data_directory) {
+            println!("[ Stage 2 ] ( Reading Current Generation )");
+            let previous_generation = match generations::read_current_generation_with_blobs(
+                &data_directory,
+                &mut blobs,
+            ) {
                 Ok(previous_generation) => previous_generation,
                 Err(err) => match err.kind() {
                     io::ErrorKind::NotFound => generations::models::Generation::new(),
@@ -54,58 +89,85 @@ fn main() -> Result<(), Box<dyn Error>> {
             };
 
             println!("[ Stage 2 ] ( Generating Current Generation )");
-            let current_generation = generations::models::Generation::from_lua_config(
+            let mut current_generation = generations::models::Generation::from_lua_config(
                 &config,
-                previous_generation.id + 1,
+                generations::next_generation_id(&data_directory)?,
                 &Local::now(),
+                &mut blobs,
             )?;
 
-            println!("[ Stage 2 ] ( Saving Current Generation )");
-            current_generation
-                .write(&data_directory.join(format!("carbide-{}", previous_generation.id + 1)))?;
-
             println!("[ Stage 3 ] ( Calculating Differences )");
-            let difference =
+            let mut difference =
                 difference::differ_generations(&previous_generation, &current_generation);
 
-            if difference.actions.len() > 0 {
-                println!("[ Stage 4 ] ( Applying Differences )");
-            } else {
-                println!("[ Stage 4 ] ( No Differences Found )");
+            if dry_run {
+                println!("[ Stage 4 ] ( Planned Differences )");
+                print!(
+                    "{}",
+                    difference::format_difference(
+                        &data_directory,
+                        &blobs,
+                        &previous_generation,
+                        &difference
+                    )?
+                );
+                return Ok(());
             }
 
-            for action in &difference.actions {
-                match action {
-                    difference::models::Action::File(file) => match file {
-                        difference::models::File::Create { path, content } => {
-                            println!("[ Stage 4 ] ( Creating File ) {}", path.display());
-
-                            let mut file = File::create(&path)?;
-                            file.write_all(content.as_bytes())?;
-                        }
-                        difference::models::File::Update { path, content } => {
-                            println!("[ Stage 4 ] ( Updating File ) {}", path.display());
-
-                            let mut file =
-                                OpenOptions::new().write(true).truncate(true).open(&path)?;
-                            file.write_all(content.as_bytes())?;
-                        }
-                        difference::models::File::Delete { path } => {
-                            println!("[ Stage 4 ] ( Deleting File ) {}", path.display());
-
-                            remove_file(&path)?;
-                        }
-                    },
-                    difference::models::Action::Script(script) => {
-                        for command in script {
-                            println!("[ Stage 4 ] ( Running Command ) {}", command);
-                        }
-                    }
-                }
-            }
+            let shell = execution::models::Shell::new(PathBuf::from(
+                subcommand
+                    .get_one::<String>("shell")
+                    .unwrap_or(&String::from(DEFAULT_SHELL)),
+            ));
+
+            println!("[ Stage 4 ] ( Shell ) {}", shell.program.display());
+
+            let options = apply::models::Options {
+                shell,
+                working_directory: config_directory.clone(),
+                data_directory: data_directory.clone(),
+                generation_id: current_generation.id,
+                rollback_on_failure: !subcommand.get_flag("no-rollback"),
+                package_managers: packages::system_package_managers(),
+                service_manager: Box::new(services::Systemctl),
+            };
+
+            apply_generation(
+                drift_resolution(subcommand),
+                &blobs,
+                &previous_generation,
+                &mut current_generation,
+                &mut difference,
+                &options,
+            )?;
 
             println!("[ Stage 5 ] ( Complete )");
         }
+        Some(("check", subcommand)) => {
+            let data_directory = PathBuf::from(
+                subcommand
+                    .get_one::<String>("data-directory")
+                    .unwrap_or(&String::from(DEFAULT_DATA_DIRECTORY)),
+            );
+
+            let generation = generations::read_current_generation(&data_directory)?;
+            let drifts = drift::check_generation(&generation)?;
+
+            for drift in &drifts {
+                println!("{}", drift);
+            }
+
+            if !drifts.is_empty() {
+                return Err(format!(
+                    "Found {} drifted paths in generation {}",
+                    drifts.len(),
+                    generation.id
+                )
+                .into());
+            }
+
+            println!("No drift found in generation {}", generation.id);
+        }
         Some(("generation", subcommand)) => match subcommand.subcommand() {
             Some(("list", subcommand)) => {
                 let data_directory = PathBuf::from(
@@ -113,19 +175,209 @@ fn main() -> Result<(), Box<dyn Error>> {
                         .get_one::<String>("data-directory")
                         .unwrap_or(&String::from(DEFAULT_DATA_DIRECTORY)),
                 );
-                for generation in generations::read_generations(&data_directory)? {
+                let active_generation_id = read_active_generation_id(&data_directory)?;
+
+                for id in generations::read_generation_ids(&data_directory)? {
+                    let path = generations::generation_path(&data_directory, id);
+                    let current = if id == active_generation_id {
+                        " (current)"
+                    } else {
+                        ""
+                    };
+
+                    match generations::read_generation(&data_directory, id) {
+                        Ok(generation) => println!(
+                            "{} : {} : {} : {}{}",
+                            generation.id,
+                            generation.creation_datetime.format("%Y-%m-%d %H:%M:%S"),
+                            generation.profile.as_deref().unwrap_or("-"),
+                            path.display(),
+                            current
+                        ),
+                        Err(err) => println!("{} : ( Unreadable ) {}{}", id, err, current),
+                    }
+                }
+            }
+            Some(("rollback", subcommand)) => {
+                let data_directory = PathBuf::from(
+                    subcommand
+                        .get_one::<String>("data-directory")
+                        .unwrap_or(&String::from(DEFAULT_DATA_DIRECTORY)),
+                );
+
+                println!(
+                    "[ Stage 1 ] ( Data Directory ) {}",
+                    data_directory.display()
+                );
+
+                let config_directory = PathBuf::from(
+                    subcommand
+                        .get_one::<String>("config-directory")
+                        .unwrap_or(&String::from(DEFAULT_CONFIG_DIRECTORY)),
+                );
+
+                let shell = execution::models::Shell::new(PathBuf::from(
+                    subcommand
+                        .get_one::<String>("shell")
+                        .unwrap_or(&String::from(DEFAULT_SHELL)),
+                ));
+
+                println!("[ Stage 1 ] ( Shell ) {}", shell.program.display());
+
+                println!("[ Stage 2 ] ( Reading Current Generation )");
+                let previous_generation = generations::read_current_generation(&data_directory)?;
+
+                let target_id = match subcommand.get_one::<String>("generation-id") {
+                    Some(id) => id.parse::<i32>()?,
+                    None => generations::read_generation_ids(&data_directory)?
+                        .into_iter()
+                        .filter(|id| *id < previous_generation.id)
+                        .max()
+                        .ok_or("No previous generation to roll back to")?,
+                };
+
+                println!("[ Stage 2 ] ( Reading Target Generation ) {}", target_id);
+                let mut blobs = store::models::Blobs::default();
+                let target_generation = generations::read_generation_with_blobs(
+                    &data_directory,
+                    target_id,
+                    &mut blobs,
+                )?;
+
+                println!("[ Stage 2 ] ( Generating Current Generation )");
+                let mut current_generation = generations::models::Generation {
+                    id: generations::next_generation_id(&data_directory)?,
+                    creation_datetime: Local::now(),
+                    ..target_generation
+                };
+
+                println!("[ Stage 3 ] ( Calculating Differences )");
+                let mut difference =
+                    difference::differ_generations(&previous_generation, &current_generation);
+
+                let options = apply::models::Options {
+                    shell,
+                    working_directory: config_directory.clone(),
+                    data_directory: data_directory.clone(),
+                    generation_id: current_generation.id,
+                    rollback_on_failure: !subcommand.get_flag("no-rollback"),
+                    package_managers: packages::system_package_managers(),
+                    service_manager: Box::new(services::Systemctl),
+                };
+
+                apply_generation(
+                    drift_resolution(subcommand),
+                    &blobs,
+                    &previous_generation,
+                    &mut current_generation,
+                    &mut difference,
+                    &options,
+                )?;
+
+                println!("[ Stage 5 ] ( Complete )");
+            }
+            Some(("delete", subcommand)) => {
+                let data_directory = PathBuf::from(
+                    subcommand
+                        .get_one::<String>("data-directory")
+                        .unwrap_or(&String::from(DEFAULT_DATA_DIRECTORY)),
+                );
+
+                let id = subcommand
+                    .get_one::<String>("generation-id")
+                    .expect("Generation ID is required")
+                    .parse::<i32>()?;
+
+                if id == read_active_generation_id(&data_directory)? {
+                    return Err(format!("Cannot delete the active generation: {}", id).into());
+                }
+
+                generations::delete_generation(&data_directory, id)?;
+                println!(
+                    "Deleted {}",
+                    generations::generation_path(&data_directory, id).display()
+                );
+            }
+            Some(("clean", subcommand)) => {
+                let data_directory = PathBuf::from(
+                    subcommand
+                        .get_one::<String>("data-directory")
+                        .unwrap_or(&String::from(DEFAULT_DATA_DIRECTORY)),
+                );
+
+                let mut policy = generations::models::RetentionPolicy {
+                    keep_last: subcommand.get_one::<usize>("keep-last").copied(),
+                    keep_since: None,
+                };
+
+                if let Some(age) = subcommand.get_one::<String>("older-than") {
+                    let age = generations::models::RetentionPolicy::parse_age(age)?;
+                    policy.keep_since = Some(Local::now() - age);
+                }
+
+                if let Some(date) = subcommand.get_one::<String>("keep-since") {
+                    let date = generations::models::RetentionPolicy::parse_date(date)?;
+                    policy.keep_since = Some(match policy.keep_since {
+                        Some(keep_since) => keep_since.min(date),
+                        None => date,
+                    });
+                }
+
+                if policy == generations::models::RetentionPolicy::default() {
+                    policy.keep_last =
+                        Some(generations::models::RetentionPolicy::DEFAULT_KEEP_LAST);
                     println!(
-                        "{} : {} : {}",
-                        generation.id,
-                        generation.creation_datetime.format("%Y-%m-%d %H:%M:%S"),
-                        data_directory
-                            .join(format!("carbide-{}", generation.id))
-                            .display()
-                    )
+                        "No retention rule given, keeping the last {} generations",
+                        generations::models::RetentionPolicy::DEFAULT_KEEP_LAST
+                    );
+                }
+
+                let dry_run = subcommand.get_flag("dry-run");
+
+                let active_generation_id = read_active_generation_id(&data_directory)?;
+                let generations = generations::read_generations(&data_directory)?;
+                let expired_generations =
+                    generations::expired_generations(&generations, active_generation_id, &policy);
+                // A generation that cannot be read may still reference blobs.
+                let all_generations_readable =
+                    generations.len() == generations::read_generation_ids(&data_directory)?.len();
+
+                for generation in &expired_generations {
+                    let path = generations::generation_path(&data_directory, generation.id);
+
+                    if dry_run {
+                        println!("Would delete {}", path.display());
+                    } else {
+                        generations::delete_generation(&data_directory, generation.id)?;
+                        println!("Deleted {}", path.display());
+                    }
+                }
+
+                if !all_generations_readable {
+                    println!("Skipping blob collection: some generations could not be read");
+                    return Ok(());
+                }
+
+                let referenced_hashes = generations
+                    .iter()
+                    .filter(|generation| {
+                        !expired_generations
+                            .iter()
+                            .any(|expired_generation| expired_generation.id == generation.id)
+                    })
+                    .flat_map(|generation| &generation.files)
+                    .filter_map(|file| file.hash.as_deref())
+                    .collect();
+
+                for path in store::unreferenced_blobs(&data_directory, &referenced_hashes)? {
+                    if dry_run {
+                        println!("Would delete {}", path.display());
+                    } else {
+                        fs::remove_file(&path)?;
+                        println!("Deleted {}", path.display());
+                    }
                 }
             }
-            Some(("delete", _)) => todo!(),
-            Some(("clean", _)) => todo!(),
             _ => {}
         },
         _ => {}
@@ -133,3 +385,98 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+/// Returns the active generation ID, or -1 when no generation has been applied yet.
+fn read_active_generation_id(data_directory: &Path) -> io::Result<i32> {
+    match generations::read_current_generation_id(data_directory) {
+        Ok(id) => Ok(id),
+        Err(err) => match err.kind() {
+            io::ErrorKind::NotFound => Ok(-1),
+            _ => Err(err),
+        },
+    }
+}
+
+/// Reads how drifted files should be handled from the `--force` and `--adopt` flags.
+fn drift_resolution(subcommand: &ArgMatches) -> drift::models::Resolution {
+    if subcommand.get_flag("force") {
+        drift::models::Resolution::Force
+    } else if subcommand.get_flag("adopt") {
+        drift::models::Resolution::Adopt
+    } else {
+        drift::models::Resolution::Abort
+    }
+}
+
+/// Resolves drift, stores the contents in `blobs` and applies the difference. The current
+/// generation, including any adopted content, is only saved and marked as current once it has
+/// been applied, so that a failed apply leaves no generation behind.
+fn apply_generation(
+    resolution: drift::models::Resolution,
+    blobs: &store::models::Blobs,
+    previous_generation: &generations::models::Generation,
+    current_generation: &mut generations::models::Generation,
+    difference: &mut difference::models::Difference,
+    options: &apply::models::Options,
+) -> Result<(), Box<dyn Error>> {
+    resolve_drifts(
+        resolution,
+        &options.data_directory,
+        previous_generation,
+        current_generation,
+        difference,
+    )?;
+
+    println!("[ Stage 4 ] ( Storing File Contents )");
+    blobs.write(&options.data_directory)?;
+
+    apply::apply_difference(difference, options)?;
+
+    println!("[ Stage 4 ] ( Saving Current Generation )");
+    current_generation.write(&generations::generation_path(
+        &options.data_directory,
+        current_generation.id,
+    ))?;
+
+    generations::write_current_generation_id(&options.data_directory, current_generation.id)?;
+
+    Ok(())
+}
+
+/// Stops when files about to change were modified outside carbide, unless the resolution
+/// overwrites or adopts them.
+fn resolve_drifts(
+    resolution: drift::models::Resolution,
+    data_directory: &Path,
+    previous_generation: &generations::models::Generation,
+    current_generation: &mut generations::models::Generation,
+    difference: &mut difference::models::Difference,
+) -> Result<(), Box<dyn Error>> {
+    println!("[ Stage 3 ] ( Checking For Drift )");
+    let drifts = drift::check_difference(previous_generation, difference)?;
+
+    if drifts.is_empty() {
+        return Ok(());
+    }
+
+    for drift in &drifts {
+        println!("[ Stage 3 ] {}", drift);
+    }
+
+    match resolution {
+        drift::models::Resolution::Force => {
+            println!("[ Stage 3 ] ( Overwriting Local Changes )");
+            Ok(())
+        }
+        drift::models::Resolution::Adopt => {
+            println!("[ Stage 3 ] ( Adopting Local Changes )");
+            drift::adopt_drifts(data_directory, &drifts, current_generation, difference)?;
+            Ok(())
+        }
+        drift::models::Resolution::Abort => Err(format!(
+            "{} managed files were modified outside carbide, rerun with --force to overwrite or --adopt to keep them",
+            drifts.len()
+        )
+        .into()),
+    }
+}
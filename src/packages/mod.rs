@@ -0,0 +1,115 @@
+use crate::facts;
+
+#[cfg(test)]
+mod tests;
+
+/// A backend that installs and removes packages. Packages are passed in batches, so that a
+/// backend can handle them in a single transaction.
+pub trait PackageManager {
+    /// Name packages refer to the backend by, e.g. `apt`.
+    fn name(&self) -> &str;
+    fn install_command(&self, packages: &[String]) -> String;
+    fn remove_command(&self, packages: &[String]) -> String;
+}
+
+pub struct Apt;
+
+impl PackageManager for Apt {
+    fn name(&self) -> &str {
+        "apt"
+    }
+
+    fn install_command(&self, packages: &[String]) -> String {
+        format!(
+            "DEBIAN_FRONTEND=noninteractive apt-get install -y {}",
+            packages.join(" ")
+        )
+    }
+
+    fn remove_command(&self, packages: &[String]) -> String {
+        format!(
+            "DEBIAN_FRONTEND=noninteractive apt-get remove -y {}",
+            packages.join(" ")
+        )
+    }
+}
+
+pub struct Dnf;
+
+impl PackageManager for Dnf {
+    fn name(&self) -> &str {
+        "dnf"
+    }
+
+    fn install_command(&self, packages: &[String]) -> String {
+        format!("dnf install -y {}", packages.join(" "))
+    }
+
+    fn remove_command(&self, packages: &[String]) -> String {
+        format!("dnf remove -y {}", packages.join(" "))
+    }
+}
+
+pub struct Pacman;
+
+impl PackageManager for Pacman {
+    fn name(&self) -> &str {
+        "pacman"
+    }
+
+    fn install_command(&self, packages: &[String]) -> String {
+        format!("pacman -S --needed --noconfirm {}", packages.join(" "))
+    }
+
+    fn remove_command(&self, packages: &[String]) -> String {
+        format!("pacman -Rs --noconfirm {}", packages.join(" "))
+    }
+}
+
+/// A backend whose commands are given up front, followed by the package names. Used to
+/// test package handling without touching the system.
+#[cfg(test)]
+pub struct Fake {
+    pub name: String,
+    pub install: String,
+    pub remove: String,
+}
+
+#[cfg(test)]
+impl PackageManager for Fake {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn install_command(&self, packages: &[String]) -> String {
+        format!("{} {}", self.install, packages.join(" "))
+    }
+
+    fn remove_command(&self, packages: &[String]) -> String {
+        format!("{} {}", self.remove, packages.join(" "))
+    }
+}
+
+pub fn system_package_managers() -> Vec<Box<dyn PackageManager>> {
+    vec![Box::new(Apt), Box::new(Dnf), Box::new(Pacman)]
+}
+
+/// Returns the backend packages use by default on a distro.
+pub fn default_package_manager(distro: &facts::models::Distro) -> Option<&'static str> {
+    match distro.id.as_str() {
+        "debian" | "ubuntu" | "linuxmint" | "pop" | "raspbian" => Some("apt"),
+        "fedora" | "rhel" | "centos" | "rocky" | "almalinux" => Some("dnf"),
+        "arch" | "manjaro" | "endeavouros" => Some("pacman"),
+        _ => None,
+    }
+}
+
+/// Package names end up in shell commands, so only characters package managers use in names
+/// are accepted.
+pub fn is_valid_package_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '+' | '-' | '_' | ':' | '@'))
+}
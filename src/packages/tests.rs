@@ -0,0 +1,59 @@
+use crate::facts::models::Distro;
+use crate::packages::{self, PackageManager};
+
+#[test]
+fn package_manager_commands() {
+    let names = vec![String::from("neovim"), String::from("ripgrep")];
+
+    assert_eq!(
+        packages::Apt.install_command(&names),
+        "DEBIAN_FRONTEND=noninteractive apt-get install -y neovim ripgrep"
+    );
+    assert_eq!(
+        packages::Dnf.remove_command(&names),
+        "dnf remove -y neovim ripgrep"
+    );
+    assert_eq!(
+        packages::Pacman.install_command(&names),
+        "pacman -S --needed --noconfirm neovim ripgrep"
+    );
+    assert_eq!(
+        packages::system_package_managers()
+            .iter()
+            .map(|manager| manager.name())
+            .collect::<Vec<_>>(),
+        vec!["apt", "dnf", "pacman"]
+    );
+}
+
+#[test]
+fn default_package_manager() {
+    let distro = |id: &str| Distro {
+        id: String::from(id),
+        ..Distro::default()
+    };
+
+    assert_eq!(
+        packages::default_package_manager(&distro("ubuntu")),
+        Some("apt")
+    );
+    assert_eq!(
+        packages::default_package_manager(&distro("fedora")),
+        Some("dnf")
+    );
+    assert_eq!(
+        packages::default_package_manager(&distro("arch")),
+        Some("pacman")
+    );
+    assert_eq!(packages::default_package_manager(&distro("")), None);
+}
+
+#[test]
+fn is_valid_package_name() {
+    assert!(packages::is_valid_package_name("neovim"));
+    assert!(packages::is_valid_package_name("g++"));
+    assert!(packages::is_valid_package_name("libc6:amd64"));
+    assert!(!packages::is_valid_package_name(""));
+    assert!(!packages::is_valid_package_name("--purge"));
+    assert!(!packages::is_valid_package_name("neovim; rm -rf /"));
+}
@@ -0,0 +1,77 @@
+use std::path::{Path, PathBuf};
+
+use crate::difference;
+
+#[cfg(test)]
+mod tests;
+
+/// Directory unit files of managed services are written to.
+pub const UNIT_DIRECTORY: &str = "/etc/systemd/system";
+
+/// Unit types a service name may end with. Names without one of them are services.
+const UNIT_SUFFIXES: [&str; 6] = [
+    ".service", ".socket", ".timer", ".path", ".mount", ".target",
+];
+
+/// A backend that controls units, e.g. `systemctl`.
+pub trait ServiceManager {
+    /// Returns the command that carries out a service action.
+    fn command(&self, action: &difference::models::Service) -> String;
+}
+
+pub struct Systemctl;
+
+impl ServiceManager for Systemctl {
+    fn command(&self, action: &difference::models::Service) -> String {
+        format!("systemctl {}", arguments(action))
+    }
+}
+
+/// A backend that passes the systemctl arguments to the given program instead. Used to test
+/// service handling without touching the system.
+#[cfg(test)]
+pub struct Fake {
+    pub program: String,
+}
+
+#[cfg(test)]
+impl ServiceManager for Fake {
+    fn command(&self, action: &difference::models::Service) -> String {
+        format!("{} {}", self.program, arguments(action))
+    }
+}
+
+fn arguments(action: &difference::models::Service) -> String {
+    match action {
+        difference::models::Service::DaemonReload => String::from("daemon-reload"),
+        difference::models::Service::Enable { name } => format!("enable {}", name),
+        difference::models::Service::Disable { name } => format!("disable {}", name),
+        difference::models::Service::Start { name } => format!("start {}", name),
+        difference::models::Service::Stop { name } => format!("stop {}", name),
+        difference::models::Service::Restart { name } => format!("restart {}", name),
+        difference::models::Service::TryRestart { name } => format!("try-restart {}", name),
+    }
+}
+
+/// Appends `.service` to names without a unit type.
+pub fn unit_name(name: &str) -> String {
+    if UNIT_SUFFIXES.iter().any(|suffix| name.ends_with(suffix)) {
+        name.to_string()
+    } else {
+        format!("{}.service", name)
+    }
+}
+
+pub fn unit_path(name: &str) -> PathBuf {
+    Path::new(UNIT_DIRECTORY).join(name)
+}
+
+/// Unit names end up in shell commands and paths, so only characters systemd allows in unit
+/// names are accepted.
+pub fn is_valid_unit_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(['-', '.'])
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | ':' | '@'))
+}
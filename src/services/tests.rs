@@ -0,0 +1,43 @@
+use std::path::PathBuf;
+
+use crate::difference::models::Service;
+use crate::services::{self, ServiceManager};
+
+#[test]
+fn systemctl_command() {
+    assert_eq!(
+        services::Systemctl.command(&Service::DaemonReload),
+        "systemctl daemon-reload"
+    );
+    assert_eq!(
+        services::Systemctl.command(&Service::Restart {
+            name: String::from("nginx.service")
+        }),
+        "systemctl restart nginx.service"
+    );
+    assert_eq!(
+        services::Systemctl.command(&Service::TryRestart {
+            name: String::from("php-fpm.service")
+        }),
+        "systemctl try-restart php-fpm.service"
+    );
+}
+
+#[test]
+fn unit_name_and_path() {
+    assert_eq!(services::unit_name("nginx"), "nginx.service");
+    assert_eq!(services::unit_name("backup.timer"), "backup.timer");
+    assert_eq!(
+        services::unit_path("nginx.service"),
+        PathBuf::from("/etc/systemd/system/nginx.service")
+    );
+}
+
+#[test]
+fn is_valid_unit_name() {
+    assert!(services::is_valid_unit_name("nginx"));
+    assert!(services::is_valid_unit_name("getty@tty1.service"));
+    assert!(!services::is_valid_unit_name(""));
+    assert!(!services::is_valid_unit_name("../nginx"));
+    assert!(!services::is_valid_unit_name("nginx; reboot"));
+}
@@ -0,0 +1,84 @@
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::filesystem;
+
+pub mod models;
+#[cfg(test)]
+mod tests;
+
+const STORE_DIRECTORY_NAME: &str = "store";
+
+/// Returns the hex encoded SHA-256 of `content`, which names its blob in the store.
+pub fn hash(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/// Returns where the blob with `hash` is kept, e.g. `store/2cf24dba5fb0a30e...`.
+pub fn blob_path(data_directory: &Path, hash: &str) -> PathBuf {
+    data_directory.join(STORE_DIRECTORY_NAME).join(hash)
+}
+
+/// Adds `content` to the store unless an identical blob is already there, and returns its
+/// hash.
+pub fn write_blob(data_directory: &Path, content: &[u8]) -> io::Result<String> {
+    let hash = hash(content);
+    let blob_path = blob_path(data_directory, &hash);
+
+    if !blob_path.is_file() {
+        if let Some(parent) = blob_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        filesystem::write_atomically(&blob_path, content)?;
+    }
+
+    Ok(hash)
+}
+
+pub fn read_blob(data_directory: &Path, hash: &str) -> io::Result<Vec<u8>> {
+    fs::read(blob_path(data_directory, hash))
+        .map_err(|err| io::Error::new(err.kind(), format!("Error reading blob {}: {}", hash, err)))
+}
+
+/// Returns the blobs in the store that none of `referenced_hashes` point to.
+pub fn unreferenced_blobs(
+    data_directory: &Path,
+    referenced_hashes: &HashSet<&str>,
+) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(data_directory.join(STORE_DIRECTORY_NAME)) {
+        Ok(entries) => entries,
+        Err(err) => match err.kind() {
+            io::ErrorKind::NotFound => return Ok(Vec::new()),
+            _ => return Err(err),
+        },
+    };
+
+    let mut blobs = Vec::new();
+
+    for entry in entries {
+        let path = entry?.path();
+
+        let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+
+        // Skips anything that is not a blob, like the temporary file of an interrupted write.
+        if file_name.len() != 64 || !file_name.bytes().all(|b| b.is_ascii_hexdigit()) {
+            continue;
+        }
+
+        if !referenced_hashes.contains(file_name) {
+            blobs.push(path);
+        }
+    }
+
+    blobs.sort();
+
+    Ok(blobs)
+}
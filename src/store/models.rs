@@ -0,0 +1,36 @@
+use std::{collections::BTreeMap, io, path::Path};
+
+use super::{hash, read_blob, write_blob};
+
+/// File contents held in memory until they are added to the store, keyed by their hash.
+#[derive(Debug, Default, PartialEq)]
+pub struct Blobs {
+    contents: BTreeMap<String, Vec<u8>>,
+}
+
+impl Blobs {
+    /// Keeps `content` without touching the store and returns its hash.
+    pub fn add(&mut self, content: Vec<u8>) -> String {
+        let hash = hash(&content);
+        self.contents.insert(hash.clone(), content);
+        hash
+    }
+
+    /// Returns the content with `hash`, reading it from the store in `data_directory` when it
+    /// is not held in memory.
+    pub fn read(&self, data_directory: &Path, hash: &str) -> io::Result<Vec<u8>> {
+        match self.contents.get(hash) {
+            Some(content) => Ok(content.clone()),
+            None => read_blob(data_directory, hash),
+        }
+    }
+
+    /// Adds every content to the store in `data_directory`.
+    pub fn write(&self, data_directory: &Path) -> io::Result<()> {
+        for content in self.contents.values() {
+            write_blob(data_directory, content)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use std::{collections::HashSet, fs, path::PathBuf};
+
+use assert_fs::prelude::*;
+
+use crate::store::{self, models::Blobs};
+
+#[test]
+fn blob_path() {
+    assert_eq!(
+        store::blob_path(&PathBuf::from("/var/lib/carbide"), &store::hash(b"hello")),
+        PathBuf::from(
+            "/var/lib/carbide/store/2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        )
+    );
+}
+
+#[test]
+fn write_and_read_blob() {
+    let data_directory = assert_fs::TempDir::new().unwrap();
+
+    let hash = store::write_blob(data_directory.path(), b"Hello World").unwrap();
+    assert_eq!(
+        store::write_blob(data_directory.path(), b"Hello World").unwrap(),
+        hash
+    );
+
+    assert_eq!(
+        store::read_blob(data_directory.path(), &hash).unwrap(),
+        b"Hello World"
+    );
+    assert_eq!(
+        fs::read_dir(data_directory.child("store").path())
+            .unwrap()
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn unreferenced_blobs() {
+    let data_directory = assert_fs::TempDir::new().unwrap();
+    let kept_hash = store::write_blob(data_directory.path(), b"Kept").unwrap();
+    let dropped_hash = store::write_blob(data_directory.path(), b"Dropped").unwrap();
+    data_directory
+        .child("store/.unfinished.carbide-tmp")
+        .write_str("Hello World")
+        .unwrap();
+
+    assert_eq!(
+        store::unreferenced_blobs(data_directory.path(), &HashSet::from([kept_hash.as_str()]))
+            .unwrap(),
+        vec![store::blob_path(data_directory.path(), &dropped_hash)]
+    );
+}
+
+#[test]
+fn blobs() {
+    let data_directory = assert_fs::TempDir::new().unwrap();
+    let stored_hash = store::write_blob(data_directory.path(), b"Stored").unwrap();
+
+    let mut blobs = Blobs::default();
+    let hash = blobs.add(b"Hello World".to_vec());
+    assert_eq!(hash, store::hash(b"Hello World"));
+    assert_eq!(
+        blobs.read(data_directory.path(), &hash).unwrap(),
+        b"Hello World"
+    );
+    assert_eq!(
+        blobs.read(data_directory.path(), &stored_hash).unwrap(),
+        b"Stored"
+    );
+    assert!(!store::blob_path(data_directory.path(), &hash).exists());
+
+    blobs.write(data_directory.path()).unwrap();
+    assert_eq!(
+        store::read_blob(data_directory.path(), &hash).unwrap(),
+        b"Hello World"
+    );
+}
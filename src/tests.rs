@@ -0,0 +1,148 @@
+use std::{io, path::PathBuf};
+
+use assert_fs::prelude::*;
+
+use crate::{
+    apply::models::Options,
+    difference, drift,
+    execution::models::Shell,
+    generations::{
+        self,
+        models::{File, Generation, Permissions, Script},
+    },
+    services,
+    store::{self, models::Blobs},
+};
+
+#[test]
+fn apply_generation_rollback_adopt() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let data_directory = root.child("data");
+    let file = root.child("file");
+    file.write_str("Local Content").unwrap();
+
+    let generation = |id, content: &[u8]| Generation {
+        id,
+        files: vec![File {
+            path: file.to_path_buf(),
+            hash: Some(store::write_blob(data_directory.path(), content).unwrap()),
+            permissions: Permissions::default(),
+        }],
+        ..Generation::new()
+    };
+
+    let previous_generation = generation(1, b"Second Content");
+    let options = Options {
+        shell: Shell::new(PathBuf::from("/bin/sh")),
+        working_directory: root.to_path_buf(),
+        data_directory: data_directory.to_path_buf(),
+        generation_id: 2,
+        rollback_on_failure: true,
+        package_managers: Vec::new(),
+        service_manager: Box::new(services::Systemctl),
+    };
+
+    let mut current_generation = Generation {
+        id: 2,
+        ..generation(0, b"First Content")
+    };
+    let mut difference = difference::differ_generations(&previous_generation, &current_generation);
+
+    assert!(crate::apply_generation(
+        drift::models::Resolution::Abort,
+        &Blobs::default(),
+        &previous_generation,
+        &mut current_generation,
+        &mut difference,
+        &options,
+    )
+    .is_err());
+    assert!(!generations::generation_path(data_directory.path(), 2).exists());
+
+    let mut current_generation = Generation {
+        id: 2,
+        ..generation(0, b"First Content")
+    };
+    let mut difference = difference::differ_generations(&previous_generation, &current_generation);
+
+    crate::apply_generation(
+        drift::models::Resolution::Adopt,
+        &Blobs::default(),
+        &previous_generation,
+        &mut current_generation,
+        &mut difference,
+        &options,
+    )
+    .unwrap();
+
+    file.assert("Local Content");
+    assert_eq!(
+        generations::read_generation(data_directory.path(), 2)
+            .unwrap()
+            .files[0]
+            .hash,
+        Some(store::hash(b"Local Content"))
+    );
+    assert_eq!(
+        generations::read_current_generation_id(data_directory.path()).unwrap(),
+        2
+    );
+}
+
+#[test]
+fn apply_generation_failed() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let data_directory = root.child("data");
+    let file = root.child("file");
+
+    let mut blobs = Blobs::default();
+    let previous_generation = Generation::new();
+    let mut current_generation = Generation {
+        id: 0,
+        files: vec![File {
+            path: file.to_path_buf(),
+            hash: Some(blobs.add(b"Hello World".to_vec())),
+            permissions: Permissions::default(),
+        }],
+        scripts: vec![Script {
+            name: None,
+            install: vec![String::from("false")],
+            update: vec![],
+            uninstall: vec![],
+        }],
+        ..Generation::new()
+    };
+    let mut difference = difference::differ_generations(&previous_generation, &current_generation);
+
+    assert!(crate::apply_generation(
+        drift::models::Resolution::Abort,
+        &blobs,
+        &previous_generation,
+        &mut current_generation,
+        &mut difference,
+        &Options {
+            shell: Shell::new(PathBuf::from("/bin/sh")),
+            working_directory: root.to_path_buf(),
+            data_directory: data_directory.to_path_buf(),
+            generation_id: 0,
+            rollback_on_failure: true,
+            package_managers: Vec::new(),
+            service_manager: Box::new(services::Systemctl),
+        },
+    )
+    .is_err());
+
+    // Nothing is left that a later switch could mistake for an applied generation.
+    assert!(!file.exists());
+    assert!(!generations::generation_path(data_directory.path(), 0).exists());
+    assert_eq!(
+        generations::read_current_generation(data_directory.path())
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::NotFound
+    );
+    assert_eq!(
+        generations::next_generation_id(data_directory.path()).unwrap(),
+        0
+    );
+}